@@ -0,0 +1,29 @@
+//! Colors for error messages, when they're shown in a terminal
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turn colors on or off. They're off by default, so the text stays plain.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn paint(s: impl Display, code: &str) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", code, s)
+    } else {
+        s.to_string()
+    }
+}
+
+/// The `[line N, col M]` part of an error, in bold red
+pub fn pos(s: impl Display) -> String {
+    paint(s, "1;31")
+}
+
+/// The source text an error is about, in bold yellow
+pub fn lexeme(s: impl Display) -> String {
+    paint(s, "1;33")
+}
@@ -1,81 +1,577 @@
-use std::{collections::HashMap, fmt};
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::Write,
+    mem,
+    rc::Rc,
+};
 
+use crate::color;
+use crate::natives::NATIVES;
 use crate::parser::{
-    BinaryOperator, Expr, Literal, LogicalOperator, Program, Stmt, UnaryOperator, Variable,
+    BinaryOperator, Call, Expr, Function, Literal, LogicalOperator, Program, Slot, Stmt,
+    UnaryOperator, Variable, MAX_NESTING,
 };
+use crate::symbol::Symbol;
+use crate::tokenizer::{format_number, quote_string, Pos};
 
-#[derive(PartialEq, Clone)]
+#[derive(Clone)]
 pub enum Value {
     Nil,
     Bool(bool),
     Number(f64),
     String(String),
+    Function(Rc<Closure>),
+    NativeFn(NativeFn),
+    Array(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<BTreeMap<MapKey, Value>>>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+}
+
+pub struct Class {
+    pub name: Symbol,
+    pub superclass: Option<Rc<Class>>,
+    methods: HashMap<Symbol, Rc<Closure>>,
+}
+
+impl Class {
+    /// Find a method in the class or in its superclasses
+    fn find_method(&self, name: Symbol) -> Option<&Rc<Closure>> {
+        match self.methods.get(&name) {
+            Some(method) => Some(method),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    fields: RefCell<HashMap<Symbol, Value>>,
+}
+
+/// A key of a map. Only strings and numbers can be keys.
+#[derive(Clone, PartialEq)]
+pub enum MapKey {
+    Number(f64),
+    String(String),
+}
+
+impl MapKey {
+    fn new(val: &Value, pos: Pos) -> Result<Self, RuntimeError> {
+        match val {
+            // Adding 0 turns -0 into 0, so equal numbers make equal keys
+            Value::Number(x) if !x.is_nan() => Ok(MapKey::Number(x + 0.0)),
+            Value::String(s) => Ok(MapKey::String(s.clone())),
+            _ => Err(RuntimeError::new(
+                pos,
+                "Map keys must be strings or numbers (but not NaN).".into(),
+            )),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            MapKey::Number(x) => Value::Number(*x),
+            MapKey::String(s) => Value::String(s.clone()),
+        }
+    }
+}
+
+impl Eq for MapKey {}
+
+/// Numbers come before strings
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (MapKey::Number(a), MapKey::Number(b)) => a.total_cmp(b),
+            (MapKey::Number(_), MapKey::String(_)) => Ordering::Less,
+            (MapKey::String(_), MapKey::Number(_)) => Ordering::Greater,
+            (MapKey::String(a), MapKey::String(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Format a value inside an array or a map. Strings are quoted, so the output
+/// reads back as the same value.
+fn fmt_item(val: &Value, f: &mut fmt::Formatter<'_>, printing: &mut Vec<usize>) -> fmt::Result {
+    match val {
+        Value::String(s) => write!(f, "{}", quote_string(s)),
+        _ => fmt_value(val, f, printing),
+    }
+}
+
+/// A function together with the scopes it was defined in
+pub struct Closure {
+    pub function: Rc<Function>,
+    env: Environment,
+    /// An `init` method, which always returns `this`
+    is_initializer: bool,
+}
+
+impl Closure {
+    /// A method bound to an instance, which it sees as `this`
+    fn bind(&self, instance: &Rc<Instance>) -> Closure {
+        let mut env = self.env.clone();
+        env.push();
+        env.define(Symbol::this(), Value::Instance(Rc::clone(instance)));
+        Closure {
+            function: Rc::clone(&self.function),
+            env,
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+/// A function implemented in Rust
+#[derive(Clone, Copy)]
+pub struct NativeFn {
+    pub name: &'static str,
+    /// The minimal number of arguments
+    pub arity: usize,
+    pub max_arity: usize,
+    /// Gets the arguments, the position of the call, and the interpreter, for
+    /// its settings
+    pub func: fn(&[Value], Pos, &Interpreter) -> Result<Value, Interrupt>,
+}
+
+impl Value {
+    /// The name of the value's type, as returned by the `type()` native
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::NativeFn(_) => "function",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+        }
+    }
+
+    /// The value as shown in error messages. Like `Display`, except that
+    /// strings are quoted and escaped, so it's clear where they begin and end.
+    pub fn debug_repr(&self) -> String {
+        match self {
+            Value::String(s) => quote_string(s),
+            _ => self.to_string(),
+        }
+    }
+
+    /// The type and the value, for errors about the wrong type, like
+    /// `string "a"`
+    pub fn describe(&self) -> String {
+        match self {
+            Value::Nil => "nil".into(),
+            _ => format!("{} {}", self.type_name(), self.debug_repr()),
+        }
+    }
+}
+
+/// The semantics of Lox's `==`. Values of different types are never equal, so
+/// `nil == false` and `1 == "1"` are false. Numbers follow IEEE, so NaN isn't
+/// equal to itself. Arrays and maps are equal if their items are, recursively.
+/// The other objects are equal only to themselves.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        values_equal(self, other, &mut Vec::new())
+    }
+}
+
+/// `==`, where `comparing` holds the pairs of arrays or maps which are being
+/// compared further up. Meeting such a pair again means we went around a
+/// cycle, so it's equal unless something else differs.
+fn values_equal(a: &Value, b: &Value, comparing: &mut Vec<(usize, usize)>) -> bool {
+    match (a, b) {
+        (Value::Array(a), Value::Array(b)) => {
+            containers_equal(a, b, comparing, |a, b, comparing| {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| values_equal(x, y, comparing))
+            })
+        }
+        (Value::Map(a), Value::Map(b)) => containers_equal(a, b, comparing, |a, b, comparing| {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|((ka, va), (kb, vb))| ka == kb && values_equal(va, vb, comparing))
+        }),
+        (Value::Nil, Value::Nil) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+        (Value::NativeFn(a), Value::NativeFn(b)) => a.name == b.name,
+        (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+        (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+/// Compare two arrays or maps with `items_equal`, unless they're the same
+/// object or already being compared
+fn containers_equal<T>(
+    a: &Rc<RefCell<T>>,
+    b: &Rc<RefCell<T>>,
+    comparing: &mut Vec<(usize, usize)>,
+    items_equal: impl FnOnce(&T, &T, &mut Vec<(usize, usize)>) -> bool,
+) -> bool {
+    if Rc::ptr_eq(a, b) {
+        return true;
+    }
+    let pair = (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize);
+    if comparing.contains(&pair) {
+        return true;
+    }
+    comparing.push(pair);
+    let is_equal = items_equal(&a.borrow(), &b.borrow(), comparing);
+    comparing.pop();
+    is_equal
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Nil => write!(f, "nil"),
-            Value::Bool(bool) => write!(f, "{}", bool),
-            Value::Number(x) => write!(f, "{}", x),
-            Value::String(s) => write!(f, "{}", s),
+        fmt_value(self, f, &mut Vec::new())
+    }
+}
+
+/// Format a value, where `printing` holds the arrays and maps which are being
+/// formatted further up. One which contains itself is shown as `[...]` or
+/// `{...}` inside itself.
+fn fmt_value(val: &Value, f: &mut fmt::Formatter<'_>, printing: &mut Vec<usize>) -> fmt::Result {
+    match val {
+        Value::Array(items) if printing.contains(&(Rc::as_ptr(items) as usize)) => {
+            write!(f, "[...]")
+        }
+        Value::Map(entries) if printing.contains(&(Rc::as_ptr(entries) as usize)) => {
+            write!(f, "{{...}}")
+        }
+        Value::Nil => write!(f, "nil"),
+        Value::Bool(bool) => write!(f, "{}", bool),
+        Value::Number(x) => write!(f, "{}", format_number(*x)),
+        Value::String(s) => write!(f, "{}", s),
+        Value::Function(closure) if closure.function.name.as_str().is_empty() => {
+            write!(f, "<anonymous fn>")
+        }
+        Value::Function(closure) => write!(f, "<fn {}>", closure.function.name),
+        Value::NativeFn(_) => write!(f, "<native fn>"),
+        Value::Array(items) => {
+            printing.push(Rc::as_ptr(items) as usize);
+            write!(f, "[")?;
+            for (i, item) in items.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                fmt_item(item, f, printing)?;
+            }
+            printing.pop();
+            write!(f, "]")
+        }
+        Value::Class(class) => write!(f, "{}", class.name),
+        Value::Instance(instance) => write!(f, "{} instance", instance.class.name),
+        Value::Map(entries) => {
+            printing.push(Rc::as_ptr(entries) as usize);
+            write!(f, "{{")?;
+            for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                fmt_item(&key.to_value(), f, printing)?;
+                write!(f, ": ")?;
+                fmt_item(value, f, printing)?;
+            }
+            printing.pop();
+            write!(f, "}}")
         }
     }
 }
 
+/// How control leaves a statement
+pub enum Flow {
+    Normal,
+    Return(Value),
+    /// Returning the result of calling a function, which the caller of the
+    /// current function runs instead of it, so tail calls don't nest
+    TailCall(Rc<Closure>, Vec<Value>, Pos),
+    Break,
+    Continue,
+}
+
 pub struct RuntimeError {
-    pub line: usize,
+    pub pos: Pos,
     pub msg: String,
 }
 
 impl RuntimeError {
-    fn new(line: usize, msg: String) -> Self {
-        Self { line, msg }
+    pub fn new(pos: Pos, msg: String) -> Self {
+        Self { pos, msg }
+    }
+}
+
+/// Why running stopped before the end
+pub enum Interrupt {
+    Error(RuntimeError),
+    /// `exit()` was called with this code. It unwinds like an error, but it
+    /// isn't one.
+    Exit(u8),
+}
+
+impl From<RuntimeError> for Interrupt {
+    fn from(err: RuntimeError) -> Self {
+        Interrupt::Error(err)
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n{}", self.msg, color::pos(format!("[{}]", self.pos)))
     }
 }
 
-fn to_bool(val: &Value) -> bool {
+/// Which values are falsy
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Truthiness {
+    /// Only `nil` and `false`, as in Lox
+    Strict,
+    /// Also `0`, `""`, and empty arrays and maps, as in other dynamic languages
+    Loose,
+}
+
+pub fn to_bool(val: &Value, truthiness: Truthiness) -> bool {
+    let is_loose = truthiness == Truthiness::Loose;
     match val {
         Value::Nil => false,
         Value::Bool(b) => *b,
+        Value::Number(x) if is_loose => *x != 0.0,
+        Value::String(s) if is_loose => !s.is_empty(),
+        Value::Array(items) if is_loose => !items.borrow().is_empty(),
+        Value::Map(entries) if is_loose => !entries.borrow().is_empty(),
         _ => true,
     }
 }
 
-fn expect_number(val: &Value, line: usize) -> Result<f64, RuntimeError> {
+/// Get a number. `what` names the value in the error, like "Left operand".
+pub fn expect_number(val: &Value, what: &str, pos: Pos) -> Result<f64, RuntimeError> {
     match val {
         Value::Number(x) => Ok(*x),
-        _ => Err(RuntimeError::new(line, "Expecting a number".into())),
+        _ => Err(RuntimeError::new(
+            pos,
+            format!("{} must be a number, got {}.", what, val.describe()),
+        )),
+    }
+}
+
+/// Fail if an arithmetic operand is nil. That's usually an uninitialized
+/// variable, so it gets its own message rather than the one about types.
+fn expect_not_nil(val: &Value, what: &str, pos: Pos) -> Result<(), RuntimeError> {
+    match val {
+        Value::Nil => Err(RuntimeError::new(
+            pos,
+            format!("{} is nil. Can't do arithmetic on nil.", what),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn expect_not_const(target: &Variable, pos: Pos, ctx: &Interpreter) -> Result<(), RuntimeError> {
+    if ctx.env.is_const(target) {
+        return Err(RuntimeError::new(
+            pos,
+            format!("Can't assign to constant '{}'.", target.name),
+        ));
+    }
+    Ok(())
+}
+
+/// Get an integer-valued number, for the bitwise operators
+pub fn expect_integer(val: &Value, what: &str, pos: Pos) -> Result<i64, RuntimeError> {
+    let x = expect_number(val, what, pos)?;
+    // i64::MAX as f64 rounds up to 2^63, which is out of range
+    if x.fract() != 0.0 || !(i64::MIN as f64..i64::MAX as f64).contains(&x) {
+        return Err(RuntimeError::new(
+            pos,
+            format!("{} must be an integer, got {}.", what, val.describe()),
+        ));
+    }
+    Ok(x as i64)
+}
+
+/// Get a valid shift amount for `<<` and `>>`
+fn expect_shift(val: &Value, pos: Pos) -> Result<u32, RuntimeError> {
+    let shift = expect_integer(val, "Shift amount", pos)?;
+    if !(0..64).contains(&shift) {
+        return Err(RuntimeError::new(
+            pos,
+            "Shift amount must be between 0 and 63".into(),
+        ));
+    }
+    Ok(shift as u32)
+}
+
+/// Get the right operand of `/` or `%`, which can only be zero with IEEE
+/// division
+fn expect_divisor(val: &Value, pos: Pos, ieee_div: bool) -> Result<f64, RuntimeError> {
+    let x = expect_number(val, "Right operand", pos)?;
+    if x == 0.0 && !ieee_div {
+        return Err(RuntimeError::new(pos, "Division by zero.".into()));
+    }
+    Ok(x)
+}
+
+fn expect_array(val: &Value, pos: Pos) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match val {
+        Value::Array(items) => Ok(Rc::clone(items)),
+        _ => Err(RuntimeError::new(
+            pos,
+            "Only arrays and maps can be indexed.".into(),
+        )),
+    }
+}
+
+/// Convert an index value to a position in an array of the given length
+fn array_index(index: &Value, len: usize, pos: Pos) -> Result<usize, RuntimeError> {
+    let x = expect_number(index, "Array index", pos)?;
+    if x.fract() != 0.0 {
+        return Err(RuntimeError::new(
+            pos,
+            format!("Array index must be an integer, got {}.", format_number(x)),
+        ));
+    }
+    if x < 0.0 || x >= len as f64 {
+        return Err(RuntimeError::new(
+            pos,
+            format!(
+                "Array index {} out of range for length {}.",
+                format_number(x),
+                len
+            ),
+        ));
+    }
+    Ok(x as usize)
+}
+
+/// Order two numbers or two strings, for the comparison operators.
+/// Return None if they're unordered, which happens with NaN.
+fn compare(left: &Value, right: &Value, pos: Pos) -> Result<Option<Ordering>, RuntimeError> {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => Ok(left.partial_cmp(right)),
+        (Value::String(left), Value::String(right)) => Ok(Some(left.cmp(right))),
+        _ => Err(RuntimeError::new(
+            pos,
+            format!(
+                "Operands must be two numbers or two strings, got {} and {}.",
+                left.describe(),
+                right.describe()
+            ),
+        )),
     }
 }
 
+/// A variable's value, and whether it was declared with `const`
+struct Binding {
+    value: Value,
+    is_const: bool,
+}
+
+/// A local scope holds its variables in the order they were declared, which
+/// is the order of their slots. It's shared, since closures may keep it alive
+/// after its block ends.
+type Scope = Rc<RefCell<Vec<Binding>>>;
+
+#[derive(Clone)]
 pub struct Environment {
-    /// We hold a stack of scopes. The most local is the last
-    scopes: Vec<HashMap<String, Value>>,
+    /// Globals are looked up by name, so the REPL can add them line by line
+    globals: Rc<RefCell<HashMap<Symbol, Binding>>>,
+    /// The local scopes, matching those of the resolver. The most local is the last
+    scopes: Vec<Scope>,
 }
 
 impl Environment {
-    fn get(&self, name: &String) -> Option<Value> {
-        for scope in self.scopes.iter().rev() {
-            if let Some(val) = scope.get(name) {
-                return Some(val.clone());
+    fn scope(&self, slot: Slot) -> &Scope {
+        &self.scopes[self.scopes.len() - 1 - slot.depth]
+    }
+
+    fn get_at(&self, slot: Slot) -> Value {
+        self.scope(slot).borrow()[slot.index].value.clone()
+    }
+
+    fn get(&self, variable: &Variable) -> Option<Value> {
+        match variable.slot.get() {
+            Some(slot) => Some(self.get_at(slot)),
+            None => self
+                .globals
+                .borrow()
+                .get(&variable.name)
+                .map(|global| global.value.clone()),
+        }
+    }
+
+    /// Whether the variable was declared with `const`. False if it wasn't
+    /// declared at all.
+    fn is_const(&self, variable: &Variable) -> bool {
+        match variable.slot.get() {
+            Some(slot) => self.scope(slot).borrow()[slot.index].is_const,
+            None => self
+                .globals
+                .borrow()
+                .get(&variable.name)
+                .is_some_and(|global| global.is_const),
+        }
+    }
+
+    /// Assign to a variable. Return false if it wasn't declared.
+    fn set(&mut self, variable: &Variable, val: &Value) -> bool {
+        match variable.slot.get() {
+            Some(slot) => {
+                self.scope(slot).borrow_mut()[slot.index].value = val.clone();
+                true
             }
+            None => match self.globals.borrow_mut().get_mut(&variable.name) {
+                Some(global) => {
+                    global.value = val.clone();
+                    true
+                }
+                None => false,
+            },
         }
-        None
     }
 
-    fn set(&mut self, name: &String, val: &Value) -> bool {
-        for scope in self.scopes.iter_mut().rev() {
-            if scope.contains_key(name) {
-                scope.insert(name.clone(), val.clone());
-                return true;
+    /// Define a variable in the innermost scope. A local takes the next slot.
+    fn define(&mut self, name: Symbol, val: Value) {
+        self.define_binding(name, val, false);
+    }
+
+    fn define_binding(&mut self, name: Symbol, value: Value, is_const: bool) {
+        let binding = Binding { value, is_const };
+        match self.scopes.last() {
+            Some(scope) => scope.borrow_mut().push(binding),
+            None => {
+                self.globals.borrow_mut().insert(name, binding);
             }
         }
-        false
+    }
+
+    /// Define a global before running a program, to provide it values from the host
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        let binding = Binding {
+            value,
+            is_const: false,
+        };
+        self.globals
+            .borrow_mut()
+            .insert(Symbol::intern(name), binding);
     }
 
     fn push(&mut self) {
-        self.scopes.push(HashMap::new());
+        self.scopes.push(Scope::default());
     }
 
     fn pop(&mut self) {
@@ -84,14 +580,104 @@ impl Environment {
 }
 
 impl Default for Environment {
+    /// An environment with only the globals, holding the native functions
     fn default() -> Self {
-        Environment {
-            scopes: Vec::<_>::from([HashMap::<_, _>::new()]),
+        let mut env = Environment {
+            globals: Rc::default(),
+            scopes: Vec::new(),
+        };
+        for native in NATIVES {
+            env.define(Symbol::intern(native.name), Value::NativeFn(*native));
         }
+        env
     }
 }
 
-pub fn evaluate(expr: &Expr, ctx: &mut Environment) -> Result<Value, RuntimeError> {
+/// The default limit on nested function calls
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// The state of a running program
+pub struct Interpreter<'a> {
+    env: Environment,
+    /// Where `print` writes to
+    out: &'a mut dyn Write,
+    /// The number of function calls currently running
+    depth: usize,
+    /// How deep the expression being evaluated is, within the current call
+    nesting: usize,
+    /// Calling deeper than this is a runtime error, rather than a crash
+    pub max_depth: usize,
+    /// Whether to print each statement to stderr before running it
+    pub trace: bool,
+    /// Whether dividing by zero gives infinity or NaN, as in IEEE 754, rather
+    /// than a runtime error
+    pub ieee_div: bool,
+    /// Whether assigning to an undeclared variable defines a global, rather
+    /// than being a runtime error
+    pub implicit_globals: bool,
+    /// Which values conditions and logical operators treat as false
+    pub truthiness: Truthiness,
+    /// Whether to count how many times each kind of node runs
+    pub profile: bool,
+    /// The counts, by "expr" or "stmt" and the node's kind
+    profile_counts: HashMap<(&'static str, &'static str), u64>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        Self::with_env(out, Environment::default())
+    }
+
+    /// An interpreter starting with the given globals, instead of only the natives
+    pub fn with_env(out: &'a mut dyn Write, env: Environment) -> Self {
+        Interpreter {
+            env,
+            out,
+            depth: 0,
+            nesting: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            trace: false,
+            ieee_div: false,
+            implicit_globals: false,
+            truthiness: Truthiness::Strict,
+            profile: false,
+            profile_counts: HashMap::new(),
+        }
+    }
+
+    /// A table of how many times each kind of node ran, most frequent first
+    pub fn profile_report(&self) -> String {
+        let mut counts: Vec<_> = self.profile_counts.iter().collect();
+        counts.sort_by(|(kind_a, count_a), (kind_b, count_b)| {
+            count_b.cmp(count_a).then(kind_a.cmp(kind_b))
+        });
+        let mut lines = vec![format!("{:>12}  node", "count")];
+        for ((category, kind), count) in counts {
+            lines.push(format!("{:>12}  {} {}", count, category, kind));
+        }
+        lines.join("\n")
+    }
+
+    fn count(&mut self, category: &'static str, kind: &'static str) {
+        *self.profile_counts.entry((category, kind)).or_default() += 1;
+    }
+}
+
+pub fn evaluate(expr: &Expr, ctx: &mut Interpreter) -> Result<Value, Interrupt> {
+    // The parser limits nesting too, so this is only for trees built otherwise
+    if ctx.nesting >= MAX_NESTING {
+        return Err(RuntimeError::new(expr.pos(), "Expression too deeply nested.".into()).into());
+    }
+    ctx.nesting += 1;
+    let val = evaluate_nested(expr, ctx);
+    ctx.nesting -= 1;
+    val
+}
+
+fn evaluate_nested(expr: &Expr, ctx: &mut Interpreter) -> Result<Value, Interrupt> {
+    if ctx.profile {
+        ctx.count("expr", expr.kind());
+    }
     Ok(match expr {
         Expr::Literal(_, literal) => match literal {
             Literal::Number(x) => Value::Number(*x),
@@ -100,70 +686,35 @@ pub fn evaluate(expr: &Expr, ctx: &mut Environment) -> Result<Value, RuntimeErro
             Literal::False => Value::Bool(false),
             Literal::Nil => Value::Nil,
         },
-        Expr::Variable(line, Variable(name)) => match ctx.get(name) {
-            Some(v) => v.clone(),
+        Expr::Variable(pos, variable) => match ctx.env.get(variable) {
+            Some(v) => v,
             None => {
                 return Err(RuntimeError::new(
-                    *line,
-                    format!("Undefined variable '{}'.", name),
-                ))
+                    *pos,
+                    format!("Undefined variable '{}'.", variable.name),
+                )
+                .into())
             }
         },
-        Expr::Unary(line, unary) => {
+        Expr::Unary(pos, unary) => {
             let val = evaluate(&unary.expr, ctx)?;
             match unary.op {
-                UnaryOperator::Negative => Value::Number(-expect_number(&val, *line)?),
-                UnaryOperator::Not => Value::Bool(!to_bool(&val)),
+                UnaryOperator::Negative => {
+                    expect_not_nil(&val, "Operand", *pos)?;
+                    Value::Number(-expect_number(&val, "Operand", *pos)?)
+                }
+                UnaryOperator::Not => Value::Bool(!to_bool(&val, ctx.truthiness)),
             }
         }
         Expr::Grouping(_, grouping) => evaluate(&grouping.0, ctx)?,
-        Expr::Binary(line, binary) => {
+        Expr::Binary(pos, binary) => {
             let left = evaluate(&binary.left, ctx)?;
             let right = evaluate(&binary.right, ctx)?;
-            match binary.op {
-                BinaryOperator::Add => match left {
-                    Value::Number(left) => Value::Number(left + expect_number(&right, *line)?),
-                    Value::String(left) => {
-                        let Value::String(right) = right else {
-                            return Err(RuntimeError::new(*line, "Expecting a string".into()));
-                        };
-                        Value::String(format!("{}{}", left, right))
-                    }
-                    _ => {
-                        return Err(RuntimeError::new(
-                            *line,
-                            "Expecting a number or a string".into(),
-                        ))
-                    }
-                },
-                BinaryOperator::Sub => {
-                    Value::Number(expect_number(&left, *line)? - expect_number(&right, *line)?)
-                }
-                BinaryOperator::Mul => {
-                    Value::Number(expect_number(&left, *line)? * expect_number(&right, *line)?)
-                }
-                BinaryOperator::Div => {
-                    Value::Number(expect_number(&left, *line)? / expect_number(&right, *line)?)
-                }
-                BinaryOperator::Equal => Value::Bool(left == right),
-                BinaryOperator::NotEqual => Value::Bool(left != right),
-                BinaryOperator::Less => {
-                    Value::Bool(expect_number(&left, *line)? < expect_number(&right, *line)?)
-                }
-                BinaryOperator::LessEqual => {
-                    Value::Bool(expect_number(&left, *line)? <= expect_number(&right, *line)?)
-                }
-                BinaryOperator::Greater => {
-                    Value::Bool(expect_number(&left, *line)? > expect_number(&right, *line)?)
-                }
-                BinaryOperator::GreaterEqual => {
-                    Value::Bool(expect_number(&left, *line)? >= expect_number(&right, *line)?)
-                }
-            }
+            binary_op(&binary.op, left, right, *pos, ctx)?
         }
-        Expr::Logical(_line, logical) => {
+        Expr::Logical(_, logical) => {
             let left = evaluate(&logical.left, ctx)?;
-            let left_as_bool = to_bool(&left);
+            let left_as_bool = to_bool(&left, ctx.truthiness);
             let eval_right = match logical.op {
                 LogicalOperator::And => left_as_bool,
                 LogicalOperator::Or => !left_as_bool,
@@ -174,25 +725,457 @@ pub fn evaluate(expr: &Expr, ctx: &mut Environment) -> Result<Value, RuntimeErro
                 left
             }
         }
-        Expr::Assign(line, assign) => {
+        Expr::Sequence(_, sequence) => {
+            let mut val = Value::Nil;
+            for expr in &sequence.0 {
+                val = evaluate(expr, ctx)?;
+            }
+            val
+        }
+        Expr::Array(_, array) => {
+            let mut items = Vec::<Value>::with_capacity(array.0.len());
+            for item in &array.0 {
+                items.push(evaluate(item, ctx)?);
+            }
+            Value::Array(Rc::new(RefCell::new(items)))
+        }
+        Expr::Get(pos, get) => {
+            let object = evaluate(&get.object, ctx)?;
+            let Value::Instance(instance) = object else {
+                return Err(RuntimeError::new(
+                    *pos,
+                    format!(
+                        "Only instances have properties, got {} for '.{}'.",
+                        object.describe(),
+                        get.name
+                    ),
+                )
+                .into());
+            };
+            get_property(&instance, get.name, *pos)?
+        }
+        Expr::Set(pos, set) => {
+            let object = evaluate(&set.object, ctx)?;
+            let Value::Instance(instance) = object else {
+                return Err(RuntimeError::new(
+                    *pos,
+                    format!(
+                        "Only instances have fields, got {} for '.{}'.",
+                        object.describe(),
+                        set.name
+                    ),
+                )
+                .into());
+            };
+            let val = evaluate(&set.rhs, ctx)?;
+            let val = match &set.op {
+                None => val,
+                Some(op) => {
+                    binary_op(op, get_property(&instance, set.name, *pos)?, val, *pos, ctx)?
+                }
+            };
+            instance.fields.borrow_mut().insert(set.name, val.clone());
+            val
+        }
+        Expr::Super(pos, sup) => {
+            // The parser makes sure it's in a method of a subclass, so `super` is
+            // defined, and `this` is defined in the scope just inside it
+            let (Some(slot), Some(Value::Class(superclass))) =
+                (sup.superclass.slot.get(), ctx.env.get(&sup.superclass))
+            else {
+                return Err(RuntimeError::new(*pos, "'super' is not bound.".into()).into());
+            };
+            let this_slot = Slot {
+                depth: slot.depth - 1,
+                index: 0,
+            };
+            let Value::Instance(this) = ctx.env.get_at(this_slot) else {
+                return Err(RuntimeError::new(*pos, "'this' is not bound.".into()).into());
+            };
+            match superclass.find_method(sup.method) {
+                Some(method) => Value::Function(Rc::new(method.bind(&this))),
+                None => {
+                    return Err(RuntimeError::new(
+                        *pos,
+                        format!("Undefined property '{}'.", sup.method),
+                    )
+                    .into())
+                }
+            }
+        }
+        Expr::This(pos, this) => ctx
+            .env
+            .get(this)
+            .ok_or_else(|| RuntimeError::new(*pos, "'this' is not bound.".into()))?,
+        Expr::Map(pos, map) => {
+            let mut entries = BTreeMap::<MapKey, Value>::new();
+            for (key, value) in &map.0 {
+                let key = MapKey::new(&evaluate(key, ctx)?, *pos)?;
+                entries.insert(key, evaluate(value, ctx)?);
+            }
+            Value::Map(Rc::new(RefCell::new(entries)))
+        }
+        Expr::Index(pos, index) => {
+            let object = evaluate(&index.object, ctx)?;
+            let index = evaluate(&index.index, ctx)?;
+            if let Value::Map(entries) = &object {
+                // A missing key gives nil
+                let key = MapKey::new(&index, *pos)?;
+                return Ok(entries.borrow().get(&key).cloned().unwrap_or(Value::Nil));
+            }
+            let items = expect_array(&object, *pos)?;
+            let items = items.borrow();
+            items[array_index(&index, items.len(), *pos)?].clone()
+        }
+        Expr::IndexAssign(pos, index_assign) => {
+            let object = evaluate(&index_assign.object, ctx)?;
+            let index = evaluate(&index_assign.index, ctx)?;
+            let val = evaluate(&index_assign.rhs, ctx)?;
+            if let Value::Map(entries) = &object {
+                let key = MapKey::new(&index, *pos)?;
+                let val = match &index_assign.op {
+                    None => val,
+                    Some(op) => {
+                        let current = entries.borrow().get(&key).cloned().unwrap_or(Value::Nil);
+                        binary_op(op, current, val, *pos, ctx)?
+                    }
+                };
+                entries.borrow_mut().insert(key, val.clone());
+                return Ok(val);
+            }
+            let items = expect_array(&object, *pos)?;
+            let i = array_index(&index, items.borrow().len(), *pos)?;
+            let val = match &index_assign.op {
+                None => val,
+                Some(op) => {
+                    let current = items.borrow()[i].clone();
+                    binary_op(op, current, val, *pos, ctx)?
+                }
+            };
+            items.borrow_mut()[i] = val.clone();
+            val
+        }
+        Expr::Lambda(_, function) => {
+            let closure = Closure {
+                function: Rc::clone(function),
+                env: ctx.env.clone(),
+                is_initializer: false,
+            };
+            Value::Function(Rc::new(closure))
+        }
+        Expr::Ternary(_, ternary) => {
+            if to_bool(&evaluate(&ternary.condition, ctx)?, ctx.truthiness) {
+                evaluate(&ternary.then_branch, ctx)?
+            } else {
+                evaluate(&ternary.else_branch, ctx)?
+            }
+        }
+        Expr::Assign(pos, assign) => {
             let val = evaluate(&assign.rhs, ctx)?;
-            let is_ok = ctx.set(&assign.name, &val);
-            if !is_ok {
+            expect_not_const(&assign.target, *pos, ctx)?;
+            let is_ok = ctx.env.set(&assign.target, &val);
+            if !is_ok && ctx.implicit_globals {
+                // Only globals may be undeclared, since locals are all resolved
+                ctx.env
+                    .define_global(assign.target.name.as_str(), val.clone());
+            } else if !is_ok {
                 return Err(RuntimeError::new(
-                    *line,
-                    format!("Variable '{}' not declared before assignment", assign.name),
-                ));
+                    *pos,
+                    format!(
+                        "Variable '{}' not declared before assignment",
+                        assign.target.name
+                    ),
+                )
+                .into());
             }
             val
         }
+        Expr::Increment(pos, increment) => {
+            let Some(val) = ctx.env.get(&increment.target) else {
+                return Err(RuntimeError::new(
+                    *pos,
+                    format!("Undefined variable '{}'.", increment.target.name),
+                )
+                .into());
+            };
+            expect_not_nil(&val, "Operand", *pos)?;
+            expect_not_const(&increment.target, *pos, ctx)?;
+            let new_val = Value::Number(expect_number(&val, "Operand", *pos)? + increment.delta);
+            ctx.env.set(&increment.target, &new_val);
+            val
+        }
+        Expr::Call(pos, call) => {
+            let (callee, args) = evaluate_call(call, ctx)?;
+            call_value(callee, args, *pos, ctx)?
+        }
+    })
+}
+
+fn binary_op(
+    op: &BinaryOperator,
+    left: Value,
+    right: Value,
+    pos: Pos,
+    ctx: &Interpreter,
+) -> Result<Value, RuntimeError> {
+    let is_comparison = matches!(
+        op,
+        BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::Less
+            | BinaryOperator::LessEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterEqual
+    );
+    // Adding nil to a string is fine, it's "nil"
+    let is_concat = matches!(op, BinaryOperator::Add)
+        && (matches!(left, Value::String(_)) || matches!(right, Value::String(_)));
+    if !is_comparison && !is_concat {
+        expect_not_nil(&left, "Left operand", pos)?;
+        expect_not_nil(&right, "Right operand", pos)?;
+    }
+    Ok(match op {
+        BinaryOperator::Add => match (&left, &right) {
+            (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
+            // A new array, so changing it doesn't change the operands
+            (Value::Array(left), Value::Array(right)) => {
+                let mut items = left.borrow().clone();
+                items.extend(right.borrow().iter().cloned());
+                Value::Array(Rc::new(RefCell::new(items)))
+            }
+            // If either side is a string, the other side is converted to one
+            (Value::String(_), _) | (_, Value::String(_)) => {
+                Value::String(format!("{}{}", left, right))
+            }
+            _ => {
+                return Err(RuntimeError::new(
+                    pos,
+                    format!(
+                    "Operands must be two numbers, two arrays, or include a string, got {} and {}.",
+                    left.describe(),
+                    right.describe()
+                ),
+                ))
+            }
+        },
+        BinaryOperator::Sub => Value::Number(
+            expect_number(&left, "Left operand", pos)?
+                - expect_number(&right, "Right operand", pos)?,
+        ),
+        BinaryOperator::Mul => Value::Number(
+            expect_number(&left, "Left operand", pos)?
+                * expect_number(&right, "Right operand", pos)?,
+        ),
+        BinaryOperator::Div => Value::Number(
+            expect_number(&left, "Left operand", pos)? / expect_divisor(&right, pos, ctx.ieee_div)?,
+        ),
+        BinaryOperator::Mod => Value::Number(
+            expect_number(&left, "Left operand", pos)? % expect_divisor(&right, pos, ctx.ieee_div)?,
+        ),
+        BinaryOperator::BitAnd => Value::Number(
+            (expect_integer(&left, "Left operand", pos)?
+                & expect_integer(&right, "Right operand", pos)?) as f64,
+        ),
+        BinaryOperator::BitOr => Value::Number(
+            (expect_integer(&left, "Left operand", pos)?
+                | expect_integer(&right, "Right operand", pos)?) as f64,
+        ),
+        BinaryOperator::BitXor => Value::Number(
+            (expect_integer(&left, "Left operand", pos)?
+                ^ expect_integer(&right, "Right operand", pos)?) as f64,
+        ),
+        BinaryOperator::ShiftLeft => Value::Number(
+            (expect_integer(&left, "Left operand", pos)? << expect_shift(&right, pos)?) as f64,
+        ),
+        BinaryOperator::ShiftRight => Value::Number(
+            (expect_integer(&left, "Left operand", pos)? >> expect_shift(&right, pos)?) as f64,
+        ),
+        BinaryOperator::Equal => Value::Bool(left == right),
+        BinaryOperator::NotEqual => Value::Bool(left != right),
+        BinaryOperator::Less => {
+            Value::Bool(matches!(compare(&left, &right, pos)?, Some(Ordering::Less)))
+        }
+        BinaryOperator::LessEqual => Value::Bool(matches!(
+            compare(&left, &right, pos)?,
+            Some(Ordering::Less | Ordering::Equal)
+        )),
+        BinaryOperator::Greater => Value::Bool(matches!(
+            compare(&left, &right, pos)?,
+            Some(Ordering::Greater)
+        )),
+        BinaryOperator::GreaterEqual => Value::Bool(matches!(
+            compare(&left, &right, pos)?,
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+    })
+}
+
+/// Read a field, or else get a method bound to the instance
+fn get_property(instance: &Rc<Instance>, name: Symbol, pos: Pos) -> Result<Value, RuntimeError> {
+    if let Some(val) = instance.fields.borrow().get(&name) {
+        return Ok(val.clone());
+    }
+    match instance.class.find_method(name) {
+        Some(method) => Ok(Value::Function(Rc::new(method.bind(instance)))),
+        None => Err(RuntimeError::new(
+            pos,
+            format!("Undefined property '{}'.", name),
+        )),
+    }
+}
+
+fn check_arity(min: usize, max: usize, got: usize, pos: Pos) -> Result<(), RuntimeError> {
+    if !(min..=max).contains(&got) {
+        let expected = if min == max {
+            min.to_string()
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return Err(RuntimeError::new(
+            pos,
+            format!("Expected {} arguments but got {}.", expected, got),
+        ));
+    }
+    Ok(())
+}
+
+/// Evaluate the callee and the arguments of a call
+fn evaluate_call(call: &Call, ctx: &mut Interpreter) -> Result<(Value, Vec<Value>), Interrupt> {
+    let callee = evaluate(&call.callee, ctx)?;
+    let mut args = Vec::<Value>::with_capacity(call.args.len());
+    for arg in &call.args {
+        args.push(evaluate(arg, ctx)?);
+    }
+    Ok((callee, args))
+}
+
+fn call_value(
+    callee: Value,
+    args: Vec<Value>,
+    pos: Pos,
+    ctx: &mut Interpreter,
+) -> Result<Value, Interrupt> {
+    Ok(match callee {
+        Value::Function(closure) => call_function(&closure, args, pos, ctx)?,
+        Value::NativeFn(native) => {
+            check_arity(native.arity, native.max_arity, args.len(), pos)?;
+            (native.func)(&args, pos, ctx)?
+        }
+        Value::Class(class) => {
+            let instance = Rc::new(Instance {
+                class: Rc::clone(&class),
+                fields: RefCell::default(),
+            });
+            if let Some(init) = class.find_method(Symbol::init()) {
+                call_function(&init.bind(&instance), args, pos, ctx)?;
+            } else {
+                check_arity(0, 0, args.len(), pos)?;
+            }
+            Value::Instance(instance)
+        }
+        _ => {
+            return Err(
+                RuntimeError::new(pos, "Can only call functions and classes.".into()).into(),
+            )
+        }
     })
 }
 
-pub fn interpret_stmt(stmt: &Stmt, ctx: &mut Environment) -> Result<(), RuntimeError> {
+fn call_function(
+    closure: &Closure,
+    args: Vec<Value>,
+    pos: Pos,
+    ctx: &mut Interpreter,
+) -> Result<Value, Interrupt> {
+    let n_params = closure.function.params.len();
+    check_arity(n_params, n_params, args.len(), pos)?;
+    if ctx.depth >= ctx.max_depth {
+        return Err(RuntimeError::new(pos, "Stack overflow.".into()).into());
+    }
+    // The function body sees the scopes it was defined in, not the caller's
+    let caller_env = mem::replace(&mut ctx.env, closure.env.clone());
+    let caller_nesting = mem::replace(&mut ctx.nesting, 0);
+    ctx.depth += 1;
+    let result = run_function(closure, args, ctx);
+    ctx.depth -= 1;
+    ctx.nesting = caller_nesting;
+    ctx.env = caller_env;
+    result
+}
+
+/// Run the body of a function, in its closure's scopes, and then the functions
+/// it tail-calls, in turn. Return the value of the last one.
+fn run_function(
+    closure: &Closure,
+    mut args: Vec<Value>,
+    ctx: &mut Interpreter,
+) -> Result<Value, Interrupt> {
+    let mut tail_callee: Option<Rc<Closure>> = None;
+    loop {
+        let closure = tail_callee.as_deref().unwrap_or(closure);
+        let function = &closure.function;
+        ctx.env.push();
+        for (param, arg) in function.params.iter().zip(args) {
+            ctx.env.define(*param, arg);
+        }
+        let flow = interpret_stmts(&function.body, ctx)?;
+        if closure.is_initializer {
+            // It's bound, so `this` is the only variable in its innermost scope
+            return Ok(closure.env.get_at(Slot { depth: 0, index: 0 }));
+        }
+        match flow {
+            Flow::Return(val) => return Ok(val),
+            Flow::TailCall(callee, callee_args, pos) => {
+                let n_params = callee.function.params.len();
+                check_arity(n_params, n_params, callee_args.len(), pos)?;
+                ctx.env = callee.env.clone();
+                tail_callee = Some(callee);
+                args = callee_args;
+            }
+            // The parser makes sure break and continue only appear inside loops
+            Flow::Normal | Flow::Break | Flow::Continue => return Ok(Value::Nil),
+        }
+    }
+}
+
+/// Run statements in order, stopping early if one of them doesn't flow normally
+fn interpret_stmts(stmts: &[Stmt], ctx: &mut Interpreter) -> Result<Flow, Interrupt> {
+    for stmt in stmts {
+        let flow = interpret_stmt(stmt, ctx)?;
+        if !matches!(flow, Flow::Normal) {
+            return Ok(flow);
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+/// Print a statement which is about to run to stderr, indented by the scope
+/// depth. Only the first line of a compound statement is shown, since the
+/// statements inside it are traced when they run.
+fn trace_stmt(stmt: &Stmt, depth: usize) {
+    let s = stmt.to_string();
+    let first_line = s.lines().next().unwrap_or_default();
+    let indent = "  ".repeat(depth);
+    match stmt.pos() {
+        Some(pos) => eprintln!("{}[{}] {}", indent, pos, first_line),
+        None => eprintln!("{}{}", indent, first_line),
+    }
+}
+
+pub fn interpret_stmt(stmt: &Stmt, ctx: &mut Interpreter) -> Result<Flow, Interrupt> {
+    if ctx.trace {
+        trace_stmt(stmt, ctx.env.scopes.len());
+    }
+    if ctx.profile {
+        ctx.count("stmt", stmt.kind());
+    }
     match stmt {
-        Stmt::Print(e) => {
-            let val = evaluate(e, ctx)?;
-            println!("{}", val);
+        Stmt::Print(exprs) => {
+            let mut vals = Vec::<String>::with_capacity(exprs.len());
+            for e in exprs {
+                vals.push(evaluate(e, ctx)?.to_string());
+            }
+            writeln!(ctx.out, "{}", vals.join(" ")).expect("failed writing output");
         }
         Stmt::Expr(e) => {
             // This is just for possible side effects
@@ -204,43 +1187,234 @@ pub fn interpret_stmt(stmt: &Stmt, ctx: &mut Environment) -> Result<(), RuntimeE
             else_branch,
         } => {
             let val = evaluate(condition, ctx)?;
-            if to_bool(&val) {
-                interpret_stmt(then_branch, ctx)?;
+            if to_bool(&val, ctx.truthiness) {
+                return interpret_stmt(then_branch, ctx);
             } else if let Some(else_branch) = else_branch {
-                interpret_stmt(else_branch, ctx)?;
+                return interpret_stmt(else_branch, ctx);
             }
         }
-        Stmt::While { condition, body } => loop {
+        Stmt::Switch {
+            subject,
+            cases,
+            default,
+        } => {
+            let subject = evaluate(subject, ctx)?;
+            let mut body = default.as_ref();
+            for (value, stmts) in cases {
+                if evaluate(value, ctx)? == subject {
+                    body = Some(stmts);
+                    break;
+                }
+            }
+            if let Some(stmts) = body {
+                ctx.env.push();
+                let flow = interpret_stmts(stmts, ctx);
+                ctx.env.pop();
+                // A `break` ends only the switch
+                return match flow? {
+                    Flow::Break => Ok(Flow::Normal),
+                    flow => Ok(flow),
+                };
+            }
+        }
+        Stmt::While {
+            condition,
+            body,
+            increment,
+        } => loop {
+            let val = evaluate(condition, ctx)?;
+            if !to_bool(&val, ctx.truthiness) {
+                break;
+            }
+            match interpret_stmt(body, ctx)? {
+                Flow::Normal | Flow::Continue => {}
+                Flow::Break => break,
+                flow @ (Flow::Return(_) | Flow::TailCall(..)) => return Ok(flow),
+            }
+            if let Some(increment) = increment {
+                evaluate(increment, ctx)?;
+            }
+        },
+        Stmt::DoWhile { body, condition } => loop {
+            match interpret_stmt(body, ctx)? {
+                Flow::Normal | Flow::Continue => {}
+                Flow::Break => break,
+                flow @ (Flow::Return(_) | Flow::TailCall(..)) => return Ok(flow),
+            }
             let val = evaluate(condition, ctx)?;
-            if !to_bool(&val) {
+            if !to_bool(&val, ctx.truthiness) {
                 break;
             }
-            interpret_stmt(body, ctx)?;
         },
-        Stmt::Var { name, initializer } => {
+        Stmt::Repeat { count, body } => {
+            let n = expect_integer(&evaluate(count, ctx)?, "Repeat count", count.pos())?;
+            if n < 0 {
+                return Err(RuntimeError::new(
+                    count.pos(),
+                    format!("Repeat count must not be negative, got {}.", n),
+                )
+                .into());
+            }
+            for _ in 0..n {
+                match interpret_stmt(body, ctx)? {
+                    Flow::Normal | Flow::Continue => {}
+                    Flow::Break => break,
+                    flow @ (Flow::Return(_) | Flow::TailCall(..)) => return Ok(flow),
+                }
+            }
+        }
+        Stmt::ForEach {
+            var,
+            iterable,
+            body,
+            ..
+        } => {
+            let items = match evaluate(iterable, ctx)? {
+                // Iterate over a copy, so the body may change the array
+                Value::Array(items) => items.borrow().clone(),
+                val => {
+                    return Err(RuntimeError::new(
+                        iterable.pos(),
+                        format!("Can only iterate over an array, got {}.", val.describe()),
+                    )
+                    .into())
+                }
+            };
+            for item in items {
+                ctx.env.push();
+                ctx.env.define(*var, item);
+                let flow = interpret_stmt(body, ctx);
+                ctx.env.pop();
+                match flow? {
+                    Flow::Normal | Flow::Continue => {}
+                    Flow::Break => break,
+                    flow @ (Flow::Return(_) | Flow::TailCall(..)) => return Ok(flow),
+                }
+            }
+        }
+        Stmt::Break => return Ok(Flow::Break),
+        Stmt::Empty => {}
+        Stmt::Continue => return Ok(Flow::Continue),
+        Stmt::Var {
+            name,
+            initializer,
+            is_const,
+            ..
+        } => {
             let val = if let Some(e) = initializer {
                 evaluate(e, ctx)?
             } else {
                 Value::Nil
             };
-            let n_scopes = ctx.scopes.len();
-            ctx.scopes[n_scopes - 1].insert(name.into(), val);
+            ctx.env.define_binding(*name, val, *is_const);
         }
-        Stmt::Block(stmts) => {
-            ctx.push();
-            for stmt in stmts {
-                interpret_stmt(stmt, ctx)?;
+        Stmt::Function(function) => {
+            let closure = Closure {
+                function: Rc::clone(function),
+                env: ctx.env.clone(),
+                is_initializer: false,
+            };
+            ctx.env
+                .define(function.name, Value::Function(Rc::new(closure)));
+        }
+        Stmt::Class(decl) => {
+            let superclass = match &decl.superclass {
+                None => None,
+                Some(e) => match evaluate(e, ctx)? {
+                    Value::Class(superclass) => Some(superclass),
+                    _ => {
+                        return Err(RuntimeError::new(
+                            e.pos(),
+                            "Superclass must be a class.".into(),
+                        )
+                        .into())
+                    }
+                },
+            };
+            // Methods of a subclass see `super` in a scope of its own
+            let mut env = ctx.env.clone();
+            if let Some(superclass) = &superclass {
+                env.push();
+                env.define(Symbol::super_(), Value::Class(Rc::clone(superclass)));
             }
-            ctx.pop();
+            let methods = decl
+                .methods
+                .iter()
+                .map(|method| {
+                    let closure = Closure {
+                        function: Rc::clone(method),
+                        env: env.clone(),
+                        is_initializer: method.name.as_str() == "init",
+                    };
+                    (method.name, Rc::new(closure))
+                })
+                .collect();
+            let class = Class {
+                name: decl.name,
+                superclass,
+                methods,
+            };
+            ctx.env.define(decl.name, Value::Class(Rc::new(class)));
+        }
+        Stmt::Block(stmts) => {
+            ctx.env.push();
+            let flow = interpret_stmts(stmts, ctx);
+            ctx.env.pop();
+            return flow;
+        }
+        Stmt::Return(Some(Expr::Call(pos, call))) => {
+            let (callee, args) = evaluate_call(call, ctx)?;
+            return Ok(match callee {
+                // An initializer returns `this`, not what its body returns
+                Value::Function(closure) if !closure.is_initializer => {
+                    Flow::TailCall(closure, args, *pos)
+                }
+                _ => Flow::Return(call_value(callee, args, *pos, ctx)?),
+            });
+        }
+        Stmt::Return(e) => {
+            let val = if let Some(e) = e {
+                evaluate(e, ctx)?
+            } else {
+                Value::Nil
+            };
+            return Ok(Flow::Return(val));
         }
     }
-    Ok(())
+    Ok(Flow::Normal)
 }
 
-pub fn interpret_program(program: &Program) -> Result<(), RuntimeError> {
-    let mut ctx = Environment::default();
-    for stmt in &program.stmts {
-        interpret_stmt(stmt, &mut ctx)?;
+/// Run a program. Return the code it gave to `exit()`, if it called it.
+pub fn interpret_program(
+    program: &Program,
+    ctx: &mut Interpreter,
+) -> Result<Option<u8>, RuntimeError> {
+    match interpret_stmts(&program.stmts, ctx) {
+        Ok(_) => Ok(None),
+        Err(Interrupt::Exit(code)) => Ok(Some(code)),
+        Err(Interrupt::Error(err)) => Err(err),
+    }
+}
+
+/// Run a program entered in the REPL, or with `run --echo`, printing the value
+/// of each top-level expression statement. Like `interpret_program()`, return
+/// the code given to `exit()`, if it was called.
+pub fn interpret_repl(
+    program: &Program,
+    ctx: &mut Interpreter,
+) -> Result<Option<u8>, RuntimeError> {
+    let result = program.stmts.iter().try_for_each(|stmt| {
+        if let Stmt::Expr(e) = stmt {
+            let val = evaluate(e, ctx)?;
+            writeln!(ctx.out, "{}", val).expect("failed writing output");
+        } else {
+            interpret_stmt(stmt, ctx)?;
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) => Ok(None),
+        Err(Interrupt::Exit(code)) => Ok(Some(code)),
+        Err(Interrupt::Error(err)) => Err(err),
     }
-    Ok(())
 }
@@ -0,0 +1,137 @@
+use std::fmt;
+
+pub mod color;
+pub mod interpreter;
+pub mod json;
+pub mod natives;
+pub mod parser;
+pub mod resolver;
+pub mod symbol;
+pub mod tokenizer;
+
+use interpreter::{interpret_program, Environment, Interpreter, RuntimeError, Value};
+use parser::{parse_program, ParseError};
+use resolver::{resolve, ResolveError};
+use tokenizer::{tokenize, Pos, ScanError};
+
+/// An error to report to the user, from any stage
+pub trait Diagnostic: fmt::Display {
+    /// The stage which found the error: "scan", "parse", "resolve" or "runtime"
+    fn stage(&self) -> &'static str;
+    fn pos(&self) -> Pos;
+    /// The message, without the position
+    fn message(&self) -> &str;
+
+    /// The error as a JSON object, for tools to read
+    fn to_json(&self) -> String {
+        let pos = self.pos();
+        json::object(&[
+            ("stage", json::quote(self.stage())),
+            ("line", pos.line.to_string()),
+            ("column", pos.column.to_string()),
+            ("message", json::quote(self.message())),
+        ])
+    }
+}
+
+impl Diagnostic for ScanError {
+    fn stage(&self) -> &'static str {
+        "scan"
+    }
+    fn pos(&self) -> Pos {
+        self.pos
+    }
+    fn message(&self) -> &str {
+        &self.msg
+    }
+}
+
+impl Diagnostic for ParseError {
+    fn stage(&self) -> &'static str {
+        "parse"
+    }
+    fn pos(&self) -> Pos {
+        self.pos
+    }
+    fn message(&self) -> &str {
+        &self.msg
+    }
+}
+
+impl Diagnostic for ResolveError {
+    fn stage(&self) -> &'static str {
+        "resolve"
+    }
+    fn pos(&self) -> Pos {
+        self.pos
+    }
+    fn message(&self) -> &str {
+        &self.msg
+    }
+}
+
+impl Diagnostic for RuntimeError {
+    fn stage(&self) -> &'static str {
+        "runtime"
+    }
+    fn pos(&self) -> Pos {
+        self.pos
+    }
+    fn message(&self) -> &str {
+        &self.msg
+    }
+}
+
+/// Why running a program failed
+pub enum RunError {
+    Scan(Vec<ScanError>),
+    Parse(Vec<ParseError>),
+    Resolve(Vec<ResolveError>),
+    Runtime(RuntimeError),
+    /// The program called `exit()` with a nonzero code
+    Exit(u8),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Scan(errors) => write_lines(f, errors),
+            RunError::Parse(errors) => write_lines(f, errors),
+            RunError::Resolve(errors) => write_lines(f, errors),
+            RunError::Runtime(err) => err.fmt(f),
+            RunError::Exit(code) => write!(f, "Exited with code {}.", code),
+        }
+    }
+}
+
+fn write_lines(f: &mut fmt::Formatter<'_>, errors: &[impl fmt::Display]) -> fmt::Result {
+    let lines: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+    write!(f, "{}", lines.join("\n"))
+}
+
+/// Run a program and return what it printed. A program which calls `exit(0)`
+/// succeeds, with what it printed up to that point.
+pub fn run_source(src: &str) -> Result<String, RunError> {
+    run_source_with_globals(src, Vec::new())
+}
+
+/// Run a program with extra globals defined by the host, and return what it printed
+pub fn run_source_with_globals(src: &str, globals: Vec<(&str, Value)>) -> Result<String, RunError> {
+    let (tokens, scan_errors) = tokenize(src);
+    if !scan_errors.is_empty() {
+        return Err(RunError::Scan(scan_errors));
+    }
+    let program = parse_program(&tokens).map_err(RunError::Parse)?;
+    resolve(&program).map_err(RunError::Resolve)?;
+    let mut env = Environment::default();
+    for (name, val) in globals {
+        env.define_global(name, val);
+    }
+    let mut out = Vec::<u8>::new();
+    let exit_code = interpret_program(&program, &mut Interpreter::with_env(&mut out, env))
+        .map_err(RunError::Runtime)?;
+    if let Some(code @ 1..) = exit_code {
+        return Err(RunError::Exit(code));
+    }
+    Ok(String::from_utf8(out).expect("print only writes valid UTF-8"))
+}
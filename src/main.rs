@@ -1,116 +1,695 @@
 use std::env;
 use std::fs;
+use std::io::{self, IsTerminal, Write};
 use std::process::ExitCode;
+use std::thread;
+use std::time::Instant;
 
-pub mod interpreter;
-pub mod parser;
-pub mod tokenizer;
-
-use interpreter::{evaluate, interpret_program, Environment};
-use parser::{parse_expr, parse_program};
-use tokenizer::tokenize;
-
-fn cmd_tokenize(filename: &str) -> ExitCode {
-    let file_contents = fs::read_to_string(filename).unwrap();
-    let (tokens, had_error) = tokenize(&file_contents);
-    for token in tokens {
-        println!(
-            "{} {} {}",
-            token.token_type.name(),
-            token.lexeme,
-            token.literal_str()
-        );
-    }
-    if had_error {
-        ExitCode::from(65)
+use codecrafters_interpreter::interpreter::{
+    evaluate, interpret_program, interpret_repl, Interpreter, Interrupt, Truthiness,
+    DEFAULT_MAX_DEPTH,
+};
+use codecrafters_interpreter::natives;
+use codecrafters_interpreter::parser::{
+    looks_like_program, parse_expr, parse_program, parse_repl_line, ParseError,
+};
+use codecrafters_interpreter::resolver::{resolve, resolve_expr};
+use codecrafters_interpreter::tokenizer::{tokenize_with_tab_width, DEFAULT_TAB_WIDTH};
+use codecrafters_interpreter::{color, Diagnostic};
+
+/// A line which ends a program which `run` reads from the standard input. The
+/// lines after it are left for `input()` to read.
+const END_OF_SOURCE: &str = "__END__";
+
+/// Read the source file, where `-` means the standard input
+fn read_source(filename: &str) -> String {
+    if filename == "-" {
+        io::read_to_string(io::stdin()).unwrap()
     } else {
+        fs::read_to_string(filename).unwrap()
+    }
+}
+
+/// Read a program to run. It's like `read_source()`, except that from the
+/// standard input, it stops at an `__END__` line.
+fn read_program(filename: &str) -> String {
+    if filename != "-" {
+        return read_source(filename);
+    }
+    let stdin = io::stdin();
+    let mut src = String::new();
+    let mut line = String::new();
+    while stdin.read_line(&mut line).unwrap() > 0 {
+        if line.trim_end_matches(['\r', '\n']) == END_OF_SOURCE {
+            break;
+        }
+        src.push_str(&line);
+        line.clear();
+    }
+    src
+}
+
+/// Print errors to stderr, one per line, as text or with --json-errors as JSON
+fn report(settings: &Settings, errors: &[impl Diagnostic]) {
+    for err in errors {
+        if settings.json_errors {
+            eprintln!("{}", err.to_json());
+        } else {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+fn cmd_tokenize(filename: &str, settings: &Settings, as_json: bool, offsets: bool) -> ExitCode {
+    let file_contents = read_source(filename);
+    let (tokens, scan_errors) = tokenize_with_tab_width(&file_contents, settings.tab_width);
+    report(settings, &scan_errors);
+    if as_json {
+        println!("[");
+        for (i, token) in tokens.iter().enumerate() {
+            let sep = if i + 1 < tokens.len() { "," } else { "" };
+            println!("  {}{}", token.to_json(), sep);
+        }
+        println!("]");
+    } else {
+        for token in tokens {
+            let range = if offsets {
+                format!(" {}..{}", token.start, token.end)
+            } else {
+                String::new()
+            };
+            println!(
+                "{} {} {}{}",
+                token.token_type.name(),
+                token.lexeme,
+                token.literal_str(),
+                range
+            );
+        }
+    }
+    if scan_errors.is_empty() {
         ExitCode::SUCCESS
+    } else {
+        ExitCode::from(65)
     }
 }
 
-fn cmd_parse(filename: &str) -> ExitCode {
-    let file_contents = fs::read_to_string(filename).unwrap();
-    let (tokens, had_error) = tokenize(&file_contents);
-    if had_error {
+/// How `parse` and `parse-program` print the tree
+#[derive(Clone, Copy, PartialEq)]
+enum AstFormat {
+    Sexpr,
+    Json,
+    Dot,
+    /// Print nothing, only the errors
+    Quiet,
+}
+
+fn cmd_parse(filename: &str, settings: &Settings, format: AstFormat) -> ExitCode {
+    let file_contents = read_source(filename);
+    let (tokens, scan_errors) = tokenize_with_tab_width(&file_contents, settings.tab_width);
+    report(settings, &scan_errors);
+    if !scan_errors.is_empty() {
         return ExitCode::from(65);
     }
-    let Ok(expr) = parse_expr(&tokens) else {
+    if looks_like_program(&tokens) {
+        let err = ParseError {
+            pos: tokens[0].pos(),
+            lexeme: Some(tokens[0].lexeme.to_string()),
+            msg: "`parse` expects a single expression; use `parse-program`".into(),
+        };
+        report(settings, &[err]);
         return ExitCode::from(65);
+    }
+    let expr = match parse_expr(&tokens) {
+        Ok(expr) => expr,
+        Err(err) => {
+            report(settings, &[err]);
+            return ExitCode::from(65);
+        }
     };
-    println!("{}", expr);
+    match format {
+        AstFormat::Sexpr => println!("{}", expr),
+        AstFormat::Json => println!("{}", expr.to_json()),
+        AstFormat::Dot => println!("{}", expr.to_dot()),
+        AstFormat::Quiet => {}
+    }
     ExitCode::SUCCESS
 }
 
-fn cmd_parse_program(filename: &str) -> ExitCode {
-    let file_contents = fs::read_to_string(filename).unwrap();
-    let (tokens, had_error) = tokenize(&file_contents);
-    if had_error {
+fn cmd_parse_program(filename: &str, settings: &Settings, format: AstFormat) -> ExitCode {
+    let file_contents = read_source(filename);
+    let (tokens, scan_errors) = tokenize_with_tab_width(&file_contents, settings.tab_width);
+    report(settings, &scan_errors);
+    if !scan_errors.is_empty() {
         return ExitCode::from(65);
     }
-    let Ok(expr) = parse_program(&tokens) else {
-        return ExitCode::from(65);
+    let program = match parse_program(&tokens) {
+        Ok(program) => program,
+        Err(errors) => {
+            report(settings, &errors);
+            return ExitCode::from(65);
+        }
     };
-    println!("{}", expr);
+    match format {
+        AstFormat::Sexpr => println!("{}", program),
+        AstFormat::Json => println!("{}", program.to_json()),
+        AstFormat::Dot => println!("{}", program.to_dot()),
+        AstFormat::Quiet => {}
+    }
     ExitCode::SUCCESS
 }
 
-fn cmd_evaluate(filename: &str) -> ExitCode {
-    let file_contents = fs::read_to_string(filename).unwrap();
-    let (tokens, had_error) = tokenize(&file_contents);
-    if had_error {
+fn cmd_evaluate(filename: &str, settings: &Settings) -> ExitCode {
+    let file_contents = read_source(filename);
+    let (tokens, scan_errors) = tokenize_with_tab_width(&file_contents, settings.tab_width);
+    report(settings, &scan_errors);
+    if !scan_errors.is_empty() {
         return ExitCode::from(65);
     }
-    let Ok(expr) = parse_expr(&tokens) else {
-        return ExitCode::from(65);
+    let expr = match parse_expr(&tokens) {
+        Ok(expr) => expr,
+        Err(err) => {
+            report(settings, &[err]);
+            return ExitCode::from(65);
+        }
     };
-    let maybe_val = evaluate(&expr, &mut Environment::default());
+    if let Err(errors) = resolve_expr(&expr) {
+        report(settings, &errors);
+        return ExitCode::from(65);
+    }
+    let mut stdout = io::stdout();
+    let mut ctx = Interpreter::new(&mut stdout);
+    settings.apply(&mut ctx);
+    let maybe_val = evaluate(&expr, &mut ctx);
     match maybe_val {
         Ok(val) => {
             println!("{}", val);
             ExitCode::SUCCESS
         }
-        Err(err) => {
-            eprintln!("{}\n[line {}]", err.msg, err.line);
+        Err(Interrupt::Exit(code)) => ExitCode::from(code),
+        Err(Interrupt::Error(err)) => {
+            report(settings, &[err]);
             ExitCode::from(70)
         }
     }
 }
 
-fn cmd_run(filename: &str) -> ExitCode {
-    let file_contents = fs::read_to_string(filename).unwrap();
-    let (tokens, had_error) = tokenize(&file_contents);
-    if had_error {
+fn cmd_run(filename: &str, settings: &Settings, time: bool, echo: bool) -> ExitCode {
+    let file_contents = read_program(filename);
+    let (tokens, scan_errors) = tokenize_with_tab_width(&file_contents, settings.tab_width);
+    report(settings, &scan_errors);
+    if !scan_errors.is_empty() {
         return ExitCode::from(65);
     }
-    let Ok(program) = parse_program(&tokens) else {
-        return ExitCode::from(65);
+    let program = match parse_program(&tokens) {
+        Ok(program) => program,
+        Err(errors) => {
+            report(settings, &errors);
+            return ExitCode::from(65);
+        }
     };
-    let maybe_err = interpret_program(&program);
-    if let Err(err) = maybe_err {
-        eprintln!("{}\n[line {}]", err.msg, err.line);
-        ExitCode::from(70)
+    if let Err(errors) = resolve(&program) {
+        report(settings, &errors);
+        return ExitCode::from(65);
+    }
+    let mut stdout = io::stdout().lock();
+    let mut ctx = Interpreter::new(&mut stdout);
+    settings.apply(&mut ctx);
+    let start = Instant::now();
+    let maybe_err = if echo {
+        interpret_repl(&program, &mut ctx)
     } else {
+        interpret_program(&program, &mut ctx)
+    };
+    let elapsed = start.elapsed();
+    let exit_code = match maybe_err {
+        Ok(None) => ExitCode::SUCCESS,
+        Ok(Some(code)) => ExitCode::from(code),
+        Err(err) => {
+            report(settings, &[err]);
+            ExitCode::from(70)
+        }
+    };
+    if time {
+        eprintln!("elapsed: {:.1}ms", elapsed.as_secs_f64() * 1000.0);
+    }
+    if settings.profile {
+        eprintln!("{}", ctx.profile_report());
+    }
+    exit_code
+}
+
+fn cmd_check(filename: &str, settings: &Settings) -> ExitCode {
+    let file_contents = read_source(filename);
+    let (tokens, scan_errors) = tokenize_with_tab_width(&file_contents, settings.tab_width);
+    report(settings, &scan_errors);
+    // Parse even after tokenizer errors, to report as many errors as we can
+    let program = match parse_program(&tokens) {
+        Ok(program) => program,
+        Err(errors) => {
+            report(settings, &errors);
+            return ExitCode::from(65);
+        }
+    };
+    if let Err(errors) = resolve(&program) {
+        report(settings, &errors);
+        return ExitCode::from(65);
+    }
+    if scan_errors.is_empty() {
         ExitCode::SUCCESS
+    } else {
+        ExitCode::from(65)
     }
 }
-fn main() -> ExitCode {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: {} tokenize|parse|evaluate|run <filename>", args[0]);
-        return ExitCode::FAILURE;
+
+fn cmd_repl(settings: &Settings) -> ExitCode {
+    let mut stdout = io::stdout();
+    let mut ctx = Interpreter::new(&mut stdout);
+    settings.apply(&mut ctx);
+    let stdin = io::stdin();
+    let show_prompt = stdin.is_terminal();
+    let mut line = String::new();
+    // The lines of a statement which isn't complete yet
+    let mut source = String::new();
+    loop {
+        if show_prompt {
+            print!("{}", if source.is_empty() { "> " } else { "... " });
+            io::stdout().flush().unwrap();
+        }
+        line.clear();
+        if stdin.read_line(&mut line).unwrap() == 0 && source.is_empty() {
+            break;
+        }
+        // An empty line, or the end of the input, ends an incomplete statement
+        let is_blank = line.trim().is_empty();
+        source.push_str(&line);
+        // On errors, we report them and just go on to the next line
+        let (tokens, scan_errors) = tokenize_with_tab_width(&source, settings.tab_width);
+        report(settings, &scan_errors);
+        if !scan_errors.is_empty() {
+            source.clear();
+            continue;
+        }
+        let program = match parse_repl_line(&tokens) {
+            Ok(program) => program,
+            // The statement may continue on the next line
+            Err(errors) if !is_blank && errors.iter().all(ParseError::is_at_end) => continue,
+            Err(errors) => {
+                report(settings, &errors);
+                source.clear();
+                continue;
+            }
+        };
+        source.clear();
+        if let Err(errors) = resolve(&program) {
+            report(settings, &errors);
+            continue;
+        }
+        match interpret_repl(&program, &mut ctx) {
+            Ok(None) => {}
+            Ok(Some(code)) => return ExitCode::from(code),
+            Err(err) => report(settings, &[err]),
+        }
     }
+    ExitCode::SUCCESS
+}
 
-    let command = &args[1];
-    let filename = &args[2];
+/// The subcommands, with their descriptions for `--help`
+const COMMANDS: &[(&str, &str)] = &[
+    (
+        "tokenize",
+        "Print the tokens of the file (add --json for JSON output)",
+    ),
+    (
+        "parse",
+        "Parse the file as a single expression and print it",
+    ),
+    ("parse-program", "Parse the file as a program and print it"),
+    (
+        "evaluate",
+        "Evaluate the file as a single expression and print the result",
+    ),
+    (
+        "run",
+        "Run the file as a program. From `-`, the program ends at an `__END__` line, and input() reads the lines after it",
+    ),
+    (
+        "check",
+        "Report the static errors in the file, without running it",
+    ),
+    ("repl", "Read and run lines from the standard input"),
+];
 
-    match command.as_str() {
-        "tokenize" => cmd_tokenize(filename),
-        "parse" => cmd_parse(filename),
-        "parse-program" => cmd_parse_program(filename),
-        "evaluate" => cmd_evaluate(filename),
-        "run" => cmd_run(filename),
-        _ => {
-            panic!("Unknown command: {}", command);
+const OPTIONS: &[(&str, &str)] = &[
+    ("--help", "Print this help"),
+    ("--version", "Print the version"),
+    (
+        "--no-color",
+        "Don't color errors. By default they're colored in a terminal, unless NO_COLOR is set",
+    ),
+    (
+        "--json",
+        "With tokenize, parse or parse-program, print JSON",
+    ),
+    (
+        "--offsets",
+        "With tokenize, also print the byte range of each token, as start..end",
+    ),
+    (
+        "--json-errors",
+        "Print errors as JSON objects with stage, line, column and message, one per line",
+    ),
+    (
+        "--ast-dot",
+        "With parse or parse-program, print the tree as a Graphviz DOT graph",
+    ),
+    (
+        "--quiet",
+        "With parse or parse-program, print only the errors, and not the tree",
+    ),
+    (
+        "--tab-width=N",
+        "Count columns with tab stops every N columns (default 8)",
+    ),
+    (
+        "--max-depth=N",
+        "Fail with a runtime error when calls nest deeper than N (default 1000)",
+    ),
+    (
+        "--stack-size=SIZE",
+        "Run with a native stack of SIZE bytes, such as 64M (default: enough for --max-depth with evaluate, run and repl, and 16M otherwise)",
+    ),
+    (
+        "--ieee-div",
+        "Make dividing by zero give infinity or NaN, rather than a runtime error",
+    ),
+    (
+        "--implicit-globals",
+        "Make assigning to an undeclared variable define a global",
+    ),
+    (
+        "--truthy=MODE",
+        "strict: only nil and false are falsy (the default). loose: also 0, \"\" and empty arrays and maps",
+    ),
+    (
+        "--seed=N",
+        "Seed rand() and randint() with N, so they give the same numbers on every run",
+    ),
+    (
+        "--allow-fs",
+        "Let readfile() and writefile() access files",
+    ),
+    (
+        "--echo",
+        "With run, print the value of each top-level expression statement, as the REPL does",
+    ),
+    (
+        "--time",
+        "With run, print how long the program ran to the standard error",
+    ),
+    (
+        "--profile",
+        "With run, print how many times each kind of node ran to the standard error",
+    ),
+    (
+        "--trace",
+        "Print each statement to the standard error before running it",
+    ),
+];
+
+/// The settings given on the command line
+struct Settings {
+    tab_width: usize,
+    max_depth: usize,
+    ieee_div: bool,
+    implicit_globals: bool,
+    truthiness: Truthiness,
+    trace: bool,
+    profile: bool,
+    json_errors: bool,
+}
+
+impl Settings {
+    /// Apply the settings which are about running the program
+    fn apply(&self, ctx: &mut Interpreter) {
+        ctx.max_depth = self.max_depth;
+        ctx.ieee_div = self.ieee_div;
+        ctx.implicit_globals = self.implicit_globals;
+        ctx.truthiness = self.truthiness;
+        ctx.trace = self.trace;
+        ctx.profile = self.profile;
+    }
+}
+
+/// A generous bound on the native stack used by one Lox call, which is the
+/// most in unoptimized builds
+const STACK_PER_CALL: usize = 256 * 1024;
+
+/// The least stack to run with, which is enough for the deepest nesting the
+/// parser allows, in unoptimized builds
+const MIN_STACK: usize = 16 << 20;
+
+/// Run `f` on a thread with `stack_size` bytes of stack, or by default enough
+/// for `max_depth` nested Lox calls. Pass 0 for commands which don't run code.
+///
+/// `max_depth` is the soft limit: calling deeper is a runtime error. The stack
+/// size is the hard one: running out of it aborts the process. So with an
+/// explicit stack size that is too small for `max_depth`, deep recursion
+/// crashes rather than failing cleanly.
+fn with_stack_for(
+    max_depth: usize,
+    stack_size: Option<usize>,
+    f: impl FnOnce() -> ExitCode + Send,
+) -> ExitCode {
+    let stack_size =
+        stack_size.unwrap_or_else(|| max_depth.saturating_mul(STACK_PER_CALL).max(MIN_STACK));
+    thread::scope(|scope| {
+        match thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn_scoped(scope, f)
+        {
+            Ok(handle) => handle
+                .join()
+                .unwrap_or_else(|err| std::panic::resume_unwind(err)),
+            Err(err) => {
+                eprintln!(
+                    "Can't start the interpreter with a stack of {} bytes ({}). \
+                     Try a smaller --max-depth or --stack-size.",
+                    stack_size, err
+                );
+                ExitCode::from(EX_OSERR)
+            }
+        }
+    })
+}
+
+/// Exit code for a wrong command line, as in sysexits.h
+const EX_USAGE: u8 = 64;
+
+/// Exit code for failing to get resources from the system, as in sysexits.h
+const EX_OSERR: u8 = 71;
+
+fn print_usage(program: &str) {
+    eprintln!(
+        "Usage: {} <command> <filename>, or {} repl. Use --help for details.",
+        program, program
+    );
+}
+
+fn print_help(program: &str) {
+    println!("Usage: {} <command> [<filename>]", program);
+    println!("A filename of `-` means the standard input.");
+    println!();
+    println!("Commands:");
+    for (name, description) in COMMANDS {
+        println!("  {:<18} {}", name, description);
+    }
+    println!();
+    println!("Options:");
+    for (name, description) in OPTIONS {
+        println!("  {:<18} {}", name, description);
+    }
+}
+
+/// Turn flags which take a value and are given as `--name value` into
+/// `--name=value`, so both forms work
+fn join_flag_values(args: &[String]) -> Result<Vec<String>, String> {
+    let mut joined = Vec::<String>::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let takes_value = OPTIONS
+            .iter()
+            .any(|(name, _)| name.split_once('=').is_some_and(|(flag, _)| flag == arg));
+        if takes_value {
+            let Some(value) = args.next() else {
+                return Err(format!("Missing value for {}", arg));
+            };
+            joined.push(format!("{}={}", arg, value));
+        } else {
+            joined.push(arg.clone());
         }
     }
+    Ok(joined)
+}
+
+/// Whether the flag is one of OPTIONS, with a value if it takes one
+fn is_known_flag(flag: &str) -> bool {
+    OPTIONS.iter().any(|(name, _)| match name.split_once('=') {
+        Some((name, _)) => flag
+            .strip_prefix(name)
+            .is_some_and(|value| value.starts_with('=')),
+        None => flag == *name,
+    })
+}
+
+/// The value of a `--stack-size=SIZE` flag, in bytes. SIZE may end with K, M or G.
+fn size_flag(flags: &[&str]) -> Result<Option<usize>, String> {
+    let Some(size) = flags
+        .iter()
+        .find_map(|flag| flag.strip_prefix("--stack-size="))
+    else {
+        return Ok(None);
+    };
+    let (digits, unit) = match size.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&size[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&size[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .map(Some)
+        .ok_or_else(|| format!("Invalid --stack-size: {}", size))
+}
+
+/// The value of a `--name=N` flag, or `default` if it isn't given
+fn number_flag(flags: &[&str], name: &str, default: usize) -> Result<usize, String> {
+    let prefix = format!("{}=", name);
+    match flags.iter().find_map(|flag| flag.strip_prefix(&prefix)) {
+        None => Ok(default),
+        Some(n) => n.parse().map_err(|_| format!("Invalid {}: {}", name, n)),
+    }
+}
+
+fn main() -> ExitCode {
+    let raw_args: Vec<String> = env::args().collect();
+    let all_args = match join_flag_values(&raw_args) {
+        Ok(all_args) => all_args,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            print_usage(&raw_args[0]);
+            return ExitCode::from(EX_USAGE);
+        }
+    };
+    let (flags, args): (Vec<&str>, Vec<&str>) = all_args
+        .iter()
+        .map(String::as_str)
+        .partition(|arg| arg.starts_with("--"));
+    let program = args[0];
+    if let Some(flag) = flags.iter().find(|flag| !is_known_flag(flag)) {
+        eprintln!("Unknown option: {}", flag);
+        print_usage(program);
+        return ExitCode::from(EX_USAGE);
+    }
+    if flags.contains(&"--help") {
+        print_help(program);
+        return ExitCode::SUCCESS;
+    }
+    if flags.contains(&"--version") {
+        println!("{}", env!("CARGO_PKG_VERSION"));
+        return ExitCode::SUCCESS;
+    }
+    let is_no_color_set = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    color::set_enabled(
+        io::stderr().is_terminal() && !is_no_color_set && !flags.contains(&"--no-color"),
+    );
+    let numbers = number_flag(&flags, "--max-depth", DEFAULT_MAX_DEPTH).and_then(|max_depth| {
+        let tab_width = number_flag(&flags, "--tab-width", DEFAULT_TAB_WIDTH)?;
+        Ok((max_depth, tab_width, size_flag(&flags)?))
+    });
+    let (max_depth, tab_width, stack_size) = match numbers {
+        Ok(numbers) => numbers,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            print_usage(program);
+            return ExitCode::from(EX_USAGE);
+        }
+    };
+    let truthiness = match flags.iter().find_map(|flag| flag.strip_prefix("--truthy=")) {
+        None | Some("strict") => Truthiness::Strict,
+        Some("loose") => Truthiness::Loose,
+        Some(mode) => {
+            eprintln!("Invalid --truthy: {} (expecting strict or loose)", mode);
+            print_usage(program);
+            return ExitCode::from(EX_USAGE);
+        }
+    };
+    if let Some(seed) = flags.iter().find_map(|flag| flag.strip_prefix("--seed=")) {
+        let Ok(seed) = seed.parse() else {
+            eprintln!("Invalid --seed: {}", seed);
+            print_usage(program);
+            return ExitCode::from(EX_USAGE);
+        };
+        natives::seed_random(seed);
+    }
+    natives::allow_fs(flags.contains(&"--allow-fs"));
+    let settings = Settings {
+        tab_width,
+        max_depth,
+        ieee_div: flags.contains(&"--ieee-div"),
+        implicit_globals: flags.contains(&"--implicit-globals"),
+        truthiness,
+        trace: flags.contains(&"--trace"),
+        profile: flags.contains(&"--profile"),
+        json_errors: flags.contains(&"--json-errors"),
+    };
+    let Some(&command) = args.get(1) else {
+        print_usage(program);
+        return ExitCode::from(EX_USAGE);
+    };
+    if command == "repl" {
+        return with_stack_for(max_depth, stack_size, || cmd_repl(&settings));
+    }
+    if !COMMANDS.iter().any(|(name, _)| *name == command) {
+        eprintln!("Unknown command: {}", command);
+        print_usage(program);
+        return ExitCode::from(EX_USAGE);
+    }
+    let Some(&filename) = args.get(2) else {
+        print_usage(program);
+        return ExitCode::from(EX_USAGE);
+    };
+
+    let as_json = flags.contains(&"--json");
+    let ast_format = if flags.contains(&"--quiet") {
+        AstFormat::Quiet
+    } else if flags.contains(&"--ast-dot") {
+        AstFormat::Dot
+    } else if as_json {
+        AstFormat::Json
+    } else {
+        AstFormat::Sexpr
+    };
+    // Only running code needs stack for nested calls
+    let max_calls = if command == "evaluate" || command == "run" {
+        max_depth
+    } else {
+        0
+    };
+    with_stack_for(max_calls, stack_size, || match command {
+        "tokenize" => cmd_tokenize(filename, &settings, as_json, flags.contains(&"--offsets")),
+        "parse" => cmd_parse(filename, &settings, ast_format),
+        "parse-program" => cmd_parse_program(filename, &settings, ast_format),
+        "evaluate" => cmd_evaluate(filename, &settings),
+        "run" => cmd_run(
+            filename,
+            &settings,
+            flags.contains(&"--time"),
+            flags.contains(&"--echo"),
+        ),
+        "check" => cmd_check(filename, &settings),
+        _ => unreachable!(),
+    })
 }
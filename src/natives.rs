@@ -0,0 +1,474 @@
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::interpreter::{
+    expect_integer, expect_number, to_bool, Interpreter, Interrupt, NativeFn, RuntimeError, Value,
+};
+use crate::tokenizer::Pos;
+
+/// The native functions which are defined in the global scope
+pub const NATIVES: &[NativeFn] = &[
+    NativeFn {
+        name: "clock",
+        arity: 0,
+        max_arity: 0,
+        func: clock,
+    },
+    NativeFn {
+        name: "len",
+        arity: 1,
+        max_arity: 1,
+        func: len,
+    },
+    NativeFn {
+        name: "input",
+        arity: 0,
+        max_arity: 0,
+        func: input,
+    },
+    NativeFn {
+        name: "type",
+        arity: 1,
+        max_arity: 1,
+        func: type_,
+    },
+    NativeFn {
+        name: "floor",
+        arity: 1,
+        max_arity: 1,
+        func: floor,
+    },
+    NativeFn {
+        name: "error",
+        arity: 1,
+        max_arity: 1,
+        func: error,
+    },
+    NativeFn {
+        name: "assert",
+        arity: 1,
+        max_arity: 2,
+        func: assert,
+    },
+    NativeFn {
+        name: "substr",
+        arity: 3,
+        max_arity: 3,
+        func: substr,
+    },
+    NativeFn {
+        name: "upper",
+        arity: 1,
+        max_arity: 1,
+        func: upper,
+    },
+    NativeFn {
+        name: "lower",
+        arity: 1,
+        max_arity: 1,
+        func: lower,
+    },
+    NativeFn {
+        name: "num",
+        arity: 1,
+        max_arity: 1,
+        func: num,
+    },
+    NativeFn {
+        name: "str",
+        arity: 1,
+        max_arity: 1,
+        func: str_,
+    },
+    NativeFn {
+        name: "push",
+        arity: 2,
+        max_arity: 2,
+        func: push,
+    },
+    NativeFn {
+        name: "pop",
+        arity: 1,
+        max_arity: 1,
+        func: pop,
+    },
+    NativeFn {
+        name: "rand",
+        arity: 0,
+        max_arity: 0,
+        func: rand,
+    },
+    NativeFn {
+        name: "randint",
+        arity: 2,
+        max_arity: 2,
+        func: randint,
+    },
+    NativeFn {
+        name: "exit",
+        arity: 1,
+        max_arity: 1,
+        func: exit,
+    },
+    NativeFn {
+        name: "min",
+        arity: 2,
+        max_arity: 2,
+        func: min,
+    },
+    NativeFn {
+        name: "max",
+        arity: 2,
+        max_arity: 2,
+        func: max,
+    },
+    NativeFn {
+        name: "abs",
+        arity: 1,
+        max_arity: 1,
+        func: abs,
+    },
+    NativeFn {
+        name: "sqrt",
+        arity: 1,
+        max_arity: 1,
+        func: sqrt,
+    },
+    NativeFn {
+        name: "pow",
+        arity: 2,
+        max_arity: 2,
+        func: pow,
+    },
+    NativeFn {
+        name: "readfile",
+        arity: 1,
+        max_arity: 1,
+        func: readfile,
+    },
+    NativeFn {
+        name: "writefile",
+        arity: 2,
+        max_arity: 2,
+        func: writefile,
+    },
+];
+
+fn clock(_args: &[Value], _pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(Value::Number(elapsed.as_secs_f64()))
+}
+
+fn len(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let len = match &args[0] {
+        Value::String(s) => s.chars().count(),
+        Value::Array(items) => items.borrow().len(),
+        Value::Map(entries) => entries.borrow().len(),
+        val => {
+            return Err(RuntimeError::new(
+                pos,
+                format!(
+                    "len() expects a string, an array or a map, got {}.",
+                    val.describe()
+                ),
+            )
+            .into())
+        }
+    };
+    Ok(Value::Number(len as f64))
+}
+
+/// Read a line from the standard input, without the newline, or nil at its
+/// end. When the program itself comes from the standard input, it ends at an
+/// `__END__` line, and the lines after it are left for this.
+fn input(_args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let mut s = String::new();
+    let n_read = io::stdin()
+        .read_line(&mut s)
+        .map_err(|err| RuntimeError::new(pos, format!("input() failed: {}", err)))?;
+    if n_read == 0 {
+        return Ok(Value::Nil);
+    }
+    if s.ends_with('\n') {
+        s.pop();
+        if s.ends_with('\r') {
+            s.pop();
+        }
+    }
+    Ok(Value::String(s))
+}
+
+fn type_(args: &[Value], _pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    Ok(Value::String(args[0].type_name().into()))
+}
+
+/// Round down, so integer division can be written as `floor(a / b)`.
+/// Infinity and NaN, which come from dividing by zero, are returned unchanged.
+fn floor(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    Ok(Value::Number(
+        expect_number(&args[0], "floor() argument", pos)?.floor(),
+    ))
+}
+
+fn min(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let a = expect_number(&args[0], "min() argument", pos)?;
+    let b = expect_number(&args[1], "min() argument", pos)?;
+    Ok(Value::Number(a.min(b)))
+}
+
+fn max(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let a = expect_number(&args[0], "max() argument", pos)?;
+    let b = expect_number(&args[1], "max() argument", pos)?;
+    Ok(Value::Number(a.max(b)))
+}
+
+fn abs(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    Ok(Value::Number(
+        expect_number(&args[0], "abs() argument", pos)?.abs(),
+    ))
+}
+
+/// The square root. Like the other math natives, it follows IEEE, so the root
+/// of a negative number is NaN rather than an error.
+fn sqrt(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    Ok(Value::Number(
+        expect_number(&args[0], "sqrt() argument", pos)?.sqrt(),
+    ))
+}
+
+/// `pow(base, exp)`. The exponent may be fractional or negative.
+fn pow(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let base = expect_number(&args[0], "pow() base", pos)?;
+    let exp = expect_number(&args[1], "pow() exponent", pos)?;
+    Ok(Value::Number(base.powf(exp)))
+}
+
+/// Parse a string as a decimal number, ignoring surrounding whitespace
+fn num(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let s = expect_string(&args[0], "num", pos)?.trim();
+    // Rust also parses words like "inf" and "NaN", which aren't Lox numbers
+    let is_word = s.chars().any(|c| c.is_alphabetic() && c != 'e' && c != 'E');
+    match s.parse::<f64>() {
+        Ok(x) if !is_word => Ok(Value::Number(x)),
+        _ => Err(RuntimeError::new(
+            pos,
+            format!("num() can't convert {} to a number.", args[0].debug_repr()),
+        )
+        .into()),
+    }
+}
+
+/// Convert any value to a string, as `print` shows it
+fn str_(args: &[Value], _pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    Ok(Value::String(args[0].to_string()))
+}
+
+/// Raise a runtime error with the given message
+fn error(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    Err(RuntimeError::new(pos, args[0].to_string()).into())
+}
+
+/// Stop the program, making the process exit with the given code
+fn exit(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let code = expect_integer(&args[0], "exit() code", pos)?;
+    match u8::try_from(code) {
+        Ok(code) => Err(Interrupt::Exit(code)),
+        Err(_) => Err(RuntimeError::new(
+            pos,
+            format!("exit() code must be between 0 and 255, got {}.", code),
+        )
+        .into()),
+    }
+}
+
+/// Raise a runtime error if the condition is falsey, as `if` sees it with the
+/// current --truthy mode. The message is optional.
+fn assert(args: &[Value], pos: Pos, ctx: &Interpreter) -> Result<Value, Interrupt> {
+    if to_bool(&args[0], ctx.truthiness) {
+        return Ok(Value::Nil);
+    }
+    let msg = match args.get(1) {
+        Some(msg) => msg.to_string(),
+        None => "Assertion failed".into(),
+    };
+    Err(RuntimeError::new(pos, msg).into())
+}
+
+fn expect_string<'a>(val: &'a Value, native: &str, pos: Pos) -> Result<&'a str, RuntimeError> {
+    match val {
+        Value::String(s) => Ok(s),
+        _ => Err(RuntimeError::new(
+            pos,
+            format!("{}() expects a string, got {}.", native, val.describe()),
+        )
+        .into()),
+    }
+}
+
+/// `substr(s, start, len)`. Counts chars, not bytes, and raises an error
+/// rather than clamping if the range doesn't fit in the string.
+fn substr(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let s = expect_string(&args[0], "substr", pos)?;
+    let start = expect_integer(&args[1], "substr() start", pos)?;
+    let len = expect_integer(&args[2], "substr() length", pos)?;
+    let n_chars = s.chars().count() as i64;
+    let end = start.saturating_add(len);
+    if start < 0 || len < 0 || end > n_chars {
+        return Err(RuntimeError::new(
+            pos,
+            format!(
+                "substr() range [{}, {}) is out of bounds for a string of length {}",
+                start, end, n_chars
+            ),
+        )
+        .into());
+    }
+    let sub = s.chars().skip(start as usize).take(len as usize).collect();
+    Ok(Value::String(sub))
+}
+
+fn upper(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    Ok(Value::String(
+        expect_string(&args[0], "upper", pos)?.to_uppercase(),
+    ))
+}
+
+fn lower(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    Ok(Value::String(
+        expect_string(&args[0], "lower", pos)?.to_lowercase(),
+    ))
+}
+
+fn expect_array<'a>(
+    val: &'a Value,
+    native: &str,
+    pos: Pos,
+) -> Result<&'a Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match val {
+        Value::Array(items) => Ok(items),
+        _ => Err(RuntimeError::new(
+            pos,
+            format!("{}() expects an array, got {}.", native, val.describe()),
+        )
+        .into()),
+    }
+}
+
+/// Append a value to an array, in place
+fn push(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    expect_array(&args[0], "push", pos)?
+        .borrow_mut()
+        .push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+/// Remove the last item of an array and return it
+fn pop(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    expect_array(&args[0], "pop", pos)?
+        .borrow_mut()
+        .pop()
+        .ok_or_else(|| RuntimeError::new(pos, "pop() from an empty array.".into()).into())
+}
+
+/// The state of the random number generator. Unless it's seeded, it starts
+/// from the OS randomness which std uses to seed its hash maps.
+static RANDOM_STATE: LazyLock<AtomicU64> =
+    LazyLock::new(|| AtomicU64::new(RandomState::new().build_hasher().finish()));
+
+/// Make `rand()` and `randint()` return the same sequence on every run
+pub fn seed_random(seed: u64) {
+    RANDOM_STATE.store(seed, Ordering::Relaxed);
+}
+
+/// The next random number, from SplitMix64
+fn next_random() -> u64 {
+    const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut z = RANDOM_STATE
+        .fetch_add(GAMMA, Ordering::Relaxed)
+        .wrapping_add(GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A number in [0, 1)
+fn rand(_args: &[Value], _pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    // 53 random bits, which is what an f64 can hold
+    Ok(Value::Number(
+        (next_random() >> 11) as f64 / (1u64 << 53) as f64,
+    ))
+}
+
+/// `randint(lo, hi)`, an integer in [lo, hi]
+fn randint(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    let lo = expect_integer(&args[0], "randint() low bound", pos)?;
+    let hi = expect_integer(&args[1], "randint() high bound", pos)?;
+    if lo > hi {
+        return Err(
+            RuntimeError::new(pos, format!("randint() range [{}, {}] is empty.", lo, hi)).into(),
+        );
+    }
+    let n_values = (hi as i128 - lo as i128 + 1) as u128;
+    let offset = (next_random() as u128 * n_values) >> 64;
+    Ok(Value::Number((lo as i128 + offset as i128) as f64))
+}
+
+/// Whether `readfile()` and `writefile()` may touch the file system
+static FS_ALLOWED: AtomicBool = AtomicBool::new(false);
+
+/// Let `readfile()` and `writefile()` access files, which they refuse by default
+pub fn allow_fs(allowed: bool) {
+    FS_ALLOWED.store(allowed, Ordering::Relaxed);
+}
+
+fn expect_fs_allowed(native: &str, pos: Pos) -> Result<(), RuntimeError> {
+    if FS_ALLOWED.load(Ordering::Relaxed) {
+        Ok(())
+    } else {
+        Err(RuntimeError::new(
+            pos,
+            format!(
+                "{}() needs file access, which is disabled. Run with --allow-fs to enable it.",
+                native
+            ),
+        )
+        .into())
+    }
+}
+
+fn readfile(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    expect_fs_allowed("readfile", pos)?;
+    let path = expect_string(&args[0], "readfile", pos)?;
+    let bytes = fs::read(path).map_err(|err| {
+        RuntimeError::new(
+            pos,
+            format!("readfile() failed to read {:?}: {}", path, err),
+        )
+    })?;
+    let contents = String::from_utf8(bytes).map_err(|_| {
+        RuntimeError::new(pos, format!("readfile(): {:?} isn't valid UTF-8.", path))
+    })?;
+    Ok(Value::String(contents))
+}
+
+fn writefile(args: &[Value], pos: Pos, _ctx: &Interpreter) -> Result<Value, Interrupt> {
+    expect_fs_allowed("writefile", pos)?;
+    let path = expect_string(&args[0], "writefile", pos)?;
+    let contents = expect_string(&args[1], "writefile", pos)?;
+    fs::write(path, contents).map_err(|err| {
+        RuntimeError::new(
+            pos,
+            format!("writefile() failed to write {:?}: {}", path, err),
+        )
+    })?;
+    Ok(Value::Nil)
+}
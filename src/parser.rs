@@ -1,15 +1,38 @@
+use std::cell::Cell;
 use std::fmt;
+use std::mem;
+use std::rc::Rc;
 
-use crate::tokenizer::{Token, TokenType};
+use crate::color;
+use crate::json;
+use crate::symbol::Symbol;
+use crate::tokenizer::{format_number_literal, Pos, Token, TokenType};
 
 pub enum Expr {
-    Literal(usize, Literal),
-    Variable(usize, Variable),
-    Unary(usize, Unary),
-    Binary(usize, Binary),
-    Logical(usize, Logical),
-    Grouping(usize, Grouping),
-    Assign(usize, Assign),
+    Literal(Pos, Literal),
+    Variable(Pos, Variable),
+    Unary(Pos, Unary),
+    Binary(Pos, Binary),
+    Logical(Pos, Logical),
+    Grouping(Pos, Grouping),
+    Assign(Pos, Assign),
+    Call(Pos, Call),
+    Ternary(Pos, Ternary),
+    /// The comma operator, like `a, b`. Evaluates all, and yields the last.
+    Sequence(Pos, Sequence),
+    Array(Pos, Array),
+    Index(Pos, Index),
+    IndexAssign(Pos, IndexAssign),
+    /// An anonymous function, like `fun (a, b) { return a + b; }`
+    Lambda(Pos, Rc<Function>),
+    Increment(Pos, Increment),
+    Map(Pos, Map),
+    Get(Pos, Get),
+    Set(Pos, Set),
+    /// `this`, which is a variable defined when binding a method
+    This(Pos, Variable),
+    /// `super.method`, which is the superclass's method, bound to `this`
+    Super(Pos, Super),
 }
 
 pub enum Literal {
@@ -20,7 +43,29 @@ pub enum Literal {
     Nil,
 }
 
-pub struct Variable(pub String);
+pub struct Variable {
+    pub name: Symbol,
+    /// Where the variable lives, if it's local. Set by the resolver.
+    pub slot: Cell<Option<Slot>>,
+}
+
+/// The position of a local variable: `depth` scopes out of the innermost
+/// scope, at `index` in that scope
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+impl Variable {
+    /// A variable which wasn't resolved yet
+    pub fn new(name: Symbol) -> Self {
+        Variable {
+            name,
+            slot: Cell::new(None),
+        }
+    }
+}
 
 pub struct Unary {
     pub op: UnaryOperator,
@@ -49,6 +94,12 @@ pub enum BinaryOperator {
     Sub,
     Mul,
     Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
 }
 pub struct Logical {
     pub left: Box<Expr>,
@@ -63,25 +114,143 @@ pub enum LogicalOperator {
 pub struct Grouping(pub Box<Expr>);
 
 pub struct Assign {
-    pub name: String,
+    pub target: Variable,
+    pub rhs: Box<Expr>,
+}
+
+pub struct Array(pub Vec<Expr>);
+
+/// At least two expressions
+pub struct Sequence(pub Vec<Expr>);
+
+/// Reading a property, like `object.name`
+pub struct Get {
+    pub object: Box<Expr>,
+    pub name: Symbol,
+}
+
+/// Setting a field, like `object.name = rhs`
+pub struct Set {
+    pub object: Box<Expr>,
+    pub name: Symbol,
+    /// For compound assignment like `a.x += 1`, the operator to apply
+    pub op: Option<BinaryOperator>,
     pub rhs: Box<Expr>,
 }
 
+pub struct Super {
+    /// The `super` variable, holding the superclass. `this` is in the scope
+    /// just inside it.
+    pub superclass: Variable,
+    pub method: Symbol,
+}
+
+/// A map literal, with its keys and values
+pub struct Map(pub Vec<(Expr, Expr)>);
+
+pub struct Index {
+    pub object: Box<Expr>,
+    pub index: Box<Expr>,
+}
+
+pub struct IndexAssign {
+    pub object: Box<Expr>,
+    pub index: Box<Expr>,
+    /// For compound assignment like `a[i] += 1`, the operator to apply
+    pub op: Option<BinaryOperator>,
+    pub rhs: Box<Expr>,
+}
+
+/// A postfix `x++` or `x--`, which evaluates to the old value
+pub struct Increment {
+    pub target: Variable,
+    /// 1 for `++` and -1 for `--`
+    pub delta: f64,
+}
+
+pub struct Ternary {
+    pub condition: Box<Expr>,
+    pub then_branch: Box<Expr>,
+    pub else_branch: Box<Expr>,
+}
+
+pub struct Call {
+    pub callee: Box<Expr>,
+    pub args: Vec<Expr>,
+}
+
+pub struct Function {
+    pub pos: Pos,
+    /// Empty for an anonymous function
+    pub name: Symbol,
+    pub params: Vec<Symbol>,
+    pub body: Vec<Stmt>,
+}
+
+pub struct Class {
+    pub pos: Pos,
+    pub name: Symbol,
+    /// A variable expression, naming the superclass
+    pub superclass: Option<Expr>,
+    pub methods: Vec<Rc<Function>>,
+}
+
 pub enum Stmt {
     Expr(Expr),
+    Function(Rc<Function>),
+    Class(Rc<Class>),
     IfStmt {
         condition: Expr,
         then_branch: Box<Stmt>,
         else_branch: Option<Box<Stmt>>,
     },
-    Print(Expr),
+    /// Prints the values on one line, separated by spaces. There's at least one.
+    Print(Vec<Expr>),
+    Return(Option<Expr>),
     While {
         condition: Expr,
         body: Box<Stmt>,
+        /// Evaluated after each iteration, even one ended by `continue`
+        increment: Option<Expr>,
+    },
+    /// Runs the body, and then again as long as the condition is true
+    DoWhile {
+        body: Box<Stmt>,
+        condition: Expr,
+    },
+    /// Runs the body a number of times, which is evaluated once
+    Repeat {
+        count: Expr,
+        body: Box<Stmt>,
+    },
+    /// Runs the body for each item of an array. Each iteration has a fresh
+    /// scope, with the variable bound to the item, so closures capture only it.
+    ForEach {
+        pos: Pos,
+        var: Symbol,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+    /// Runs the body of the first case whose value equals the subject, or else
+    /// the default. There's no fallthrough, and each body is a scope of its own.
+    /// A `break` in a body ends the switch, as in C, and a `continue` goes to
+    /// the enclosing loop.
+    Switch {
+        subject: Expr,
+        cases: Vec<(Expr, Vec<Stmt>)>,
+        default: Option<Vec<Stmt>>,
     },
+    Break,
+    Continue,
+    /// A lone `;`, which does nothing, as in `while (step()) ;`
+    Empty,
+    /// A `var`, or a `const`, which can't be assigned to. A const always has
+    /// an initializer.
     Var {
-        name: String,
+        pos: Pos,
+        name: Symbol,
         initializer: Option<Expr>,
+        is_const: bool,
     },
     Block(Vec<Stmt>),
 }
@@ -90,6 +259,106 @@ pub struct Program {
     pub stmts: Vec<Stmt>,
 }
 
+impl Expr {
+    pub fn pos(&self) -> Pos {
+        match self {
+            Self::Literal(pos, _)
+            | Self::Variable(pos, _)
+            | Self::Unary(pos, _)
+            | Self::Binary(pos, _)
+            | Self::Logical(pos, _)
+            | Self::Grouping(pos, _)
+            | Self::Assign(pos, _)
+            | Self::Call(pos, _)
+            | Self::Ternary(pos, _)
+            | Self::Sequence(pos, _)
+            | Self::Array(pos, _)
+            | Self::Index(pos, _)
+            | Self::IndexAssign(pos, _)
+            | Self::Lambda(pos, _)
+            | Self::Increment(pos, _)
+            | Self::Map(pos, _)
+            | Self::Get(pos, _)
+            | Self::Set(pos, _)
+            | Self::This(pos, _)
+            | Self::Super(pos, _) => *pos,
+        }
+    }
+}
+
+impl Expr {
+    /// The kind of node, as in the JSON tree. Names of several words are
+    /// kebab-case, like `index-assign`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Literal(..) => "literal",
+            Self::Variable(..) => "variable",
+            Self::Unary(..) => "unary",
+            Self::Binary(..) => "binary",
+            Self::Logical(..) => "logical",
+            Self::Grouping(..) => "grouping",
+            Self::Assign(..) => "assign",
+            Self::Call(..) => "call",
+            Self::Ternary(..) => "ternary",
+            Self::Sequence(..) => "sequence",
+            Self::Array(..) => "array",
+            Self::Index(..) => "index",
+            Self::IndexAssign(..) => "index-assign",
+            Self::Lambda(..) => "lambda",
+            Self::Increment(..) => "increment",
+            Self::Map(..) => "map",
+            Self::Get(..) => "get",
+            Self::Set(..) => "set",
+            Self::This(..) => "this",
+            Self::Super(..) => "super",
+        }
+    }
+}
+
+impl Stmt {
+    /// The kind of node, as in the JSON tree
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Expr(_) => "expr",
+            Self::Function(_) => "fun",
+            Self::Class(_) => "class",
+            Self::IfStmt { .. } => "if",
+            Self::Print(_) => "print",
+            Self::Return(_) => "return",
+            Self::While { .. } => "while",
+            Self::DoWhile { .. } => "do-while",
+            Self::Repeat { .. } => "repeat",
+            Self::ForEach { .. } => "foreach",
+            Self::Switch { .. } => "switch",
+            Self::Break => "break",
+            Self::Empty => "empty",
+            Self::Continue => "continue",
+            Self::Var { is_const: true, .. } => "const",
+            Self::Var { .. } => "var",
+            Self::Block(_) => "block",
+        }
+    }
+
+    /// Where the statement starts, if it's known
+    pub fn pos(&self) -> Option<Pos> {
+        match self {
+            Self::Expr(e) | Self::Return(Some(e)) => Some(e.pos()),
+            Self::Print(exprs) => Some(exprs[0].pos()),
+            Self::Function(function) => Some(function.pos),
+            Self::Class(class) => Some(class.pos),
+            Self::IfStmt { condition, .. }
+            | Self::While { condition, .. }
+            | Self::DoWhile { condition, .. } => Some(condition.pos()),
+            Self::Repeat { count, .. } => Some(count.pos()),
+            Self::Switch { subject, .. } => Some(subject.pos()),
+            Self::Var { pos, .. } | Self::ForEach { pos, .. } => Some(*pos),
+            Self::Return(None) | Self::Break | Self::Continue | Self::Empty | Self::Block(_) => {
+                None
+            }
+        }
+    }
+}
+
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -100,6 +369,19 @@ impl fmt::Display for Expr {
             Self::Logical(_, logical) => logical.fmt(f),
             Self::Grouping(_, grouping) => grouping.fmt(f),
             Self::Assign(_, assign) => assign.fmt(f),
+            Self::Call(_, call) => call.fmt(f),
+            Self::Ternary(_, ternary) => ternary.fmt(f),
+            Self::Sequence(_, sequence) => sequence.fmt(f),
+            Self::Array(_, array) => array.fmt(f),
+            Self::Index(_, index) => index.fmt(f),
+            Self::IndexAssign(_, index_assign) => index_assign.fmt(f),
+            Self::Lambda(_, function) => function.fmt(f),
+            Self::Increment(_, increment) => increment.fmt(f),
+            Self::Map(_, map) => map.fmt(f),
+            Self::Get(_, get) => get.fmt(f),
+            Self::Set(_, set) => set.fmt(f),
+            Self::This(_, _) => write!(f, "this"),
+            Self::Super(_, sup) => write!(f, "(super {})", sup.method),
         }
     }
 }
@@ -107,7 +389,7 @@ impl fmt::Display for Expr {
 impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Number(x) => write!(f, "{:?}", x),
+            Self::Number(x) => write!(f, "{}", format_number_literal(*x)),
             Self::String(s) => write!(f, "{}", s),
             Self::True => write!(f, "true"),
             Self::False => write!(f, "false"),
@@ -118,7 +400,7 @@ impl fmt::Display for Literal {
 
 impl fmt::Display for Variable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "(variable {})", self.0)
+        write!(f, "(variable {})", self.name)
     }
 }
 
@@ -150,6 +432,12 @@ impl fmt::Display for BinaryOperator {
             Self::Sub => write!(f, "-"),
             Self::Mul => write!(f, "*"),
             Self::Div => write!(f, "/"),
+            Self::Mod => write!(f, "%"),
+            Self::BitAnd => write!(f, "&"),
+            Self::BitOr => write!(f, "|"),
+            Self::BitXor => write!(f, "^"),
+            Self::ShiftLeft => write!(f, "<<"),
+            Self::ShiftRight => write!(f, ">>"),
         }
     }
 }
@@ -183,7 +471,120 @@ impl fmt::Display for Grouping {
 
 impl fmt::Display for Assign {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "(assign {} {})", self.name, self.rhs)
+        write!(f, "(assign {} {})", self.target.name, self.rhs)
+    }
+}
+
+impl fmt::Display for Array {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(array")?;
+        for item in &self.0 {
+            write!(f, " {}", item)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(,")?;
+        for expr in &self.0 {
+            write!(f, " {}", expr)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Get {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(get {} {})", self.object, self.name)
+    }
+}
+
+impl fmt::Display for Set {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(op) = &self.op {
+            write!(
+                f,
+                "({}= (get {} {}) {})",
+                op, self.object, self.name, self.rhs
+            )
+        } else {
+            write!(f, "(set {} {} {})", self.object, self.name, self.rhs)
+        }
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(map")?;
+        for (key, value) in &self.0 {
+            write!(f, " ({} {})", key, value)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(index {} {})", self.object, self.index)
+    }
+}
+
+impl fmt::Display for IndexAssign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(op) = &self.op {
+            write!(
+                f,
+                "({}= (index {} {}) {})",
+                op, self.object, self.index, self.rhs
+            )
+        } else {
+            write!(
+                f,
+                "(index-assign {} {} {})",
+                self.object, self.index, self.rhs
+            )
+        }
+    }
+}
+
+impl fmt::Display for Increment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = if self.delta > 0.0 { "++" } else { "--" };
+        write!(f, "(post{} {})", op, self.target.name)
+    }
+}
+
+impl fmt::Display for Ternary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "(? {} {} {})",
+            self.condition, self.then_branch, self.else_branch
+        )
+    }
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(call {}", self.callee)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params: Vec<&str> = self.params.iter().map(|p| p.as_str()).collect();
+        let header = if self.name.as_str().is_empty() {
+            format!("(lambda ({})", params.join(" "))
+        } else {
+            format!("(fun {} ({})", self.name, params.join(" "))
+        };
+        f.write_str(&nested(header, &self.body))
     }
 }
 
@@ -191,74 +592,884 @@ fn indent(s: String) -> String {
     format!("  {}", s.replace("\n", "\n  "))
 }
 
+/// A node with a header line, then each item indented on lines of its own,
+/// then the closing paren on a line of its own
+fn nested(header: String, items: &[impl fmt::Display]) -> String {
+    let mut s = header;
+    for item in items {
+        s.push('\n');
+        s.push_str(&indent(item.to_string()));
+    }
+    s.push_str("\n)");
+    s
+}
+
 impl fmt::Display for Stmt {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Stmt::Expr(e) => write!(f, "(expr {})", e),
-            Stmt::Print(e) => write!(f, "(print {})", e),
-            Stmt::Var { name, initializer } => {
+            Stmt::Function(function) => function.fmt(f),
+            Stmt::Class(class) => {
+                let header = if let Some(superclass) = &class.superclass {
+                    format!("(class {} < {}", class.name, superclass)
+                } else {
+                    format!("(class {}", class.name)
+                };
+                f.write_str(&nested(header, &class.methods))
+            }
+            Stmt::Print(exprs) => {
+                write!(f, "(print")?;
+                for e in exprs {
+                    write!(f, " {}", e)?;
+                }
+                write!(f, ")")
+            }
+            Stmt::Return(e) => {
+                if let Some(e) = e {
+                    write!(f, "(return {})", e)
+                } else {
+                    write!(f, "(return)")
+                }
+            }
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+                ..
+            } => {
+                let keyword = if *is_const { "const" } else { "var" };
                 if let Some(e) = initializer {
-                    write!(f, "(var {} {})", name, e)
+                    write!(f, "({} {} {})", keyword, name, e)
                 } else {
-                    write!(f, "(var {})", name)
+                    write!(f, "({} {})", keyword, name)
                 }
             }
+            Stmt::Block(stmts) => f.write_str(&nested("(block".into(), stmts)),
+            Stmt::IfStmt {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if let Some(else_branch) = else_branch {
+                    write!(f, "(if {} {} {})", condition, then_branch, else_branch)
+                } else {
+                    write!(f, "(if {} {})", condition, then_branch)
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                if let Some(increment) = increment {
+                    write!(
+                        f,
+                        "(while {} {} (increment {}))",
+                        condition, body, increment
+                    )
+                } else {
+                    write!(f, "(while {} {})", condition, body)
+                }
+            }
+            Stmt::DoWhile { body, condition } => write!(f, "(do {} {})", body, condition),
+            Stmt::Repeat { count, body } => write!(f, "(repeat {} {})", count, body),
+            Stmt::ForEach {
+                var,
+                iterable,
+                body,
+                ..
+            } => write!(f, "(foreach {} {} {})", var, iterable, body),
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                let mut items: Vec<String> = cases
+                    .iter()
+                    .map(|(value, stmts)| nested(format!("(case {}", value), stmts))
+                    .collect();
+                if let Some(stmts) = default {
+                    items.push(nested("(default".into(), stmts));
+                }
+                f.write_str(&nested(format!("(switch {}", subject), &items))
+            }
+            Stmt::Break => write!(f, "(break)"),
+            Stmt::Continue => write!(f, "(continue)"),
+            Stmt::Empty => write!(f, "(empty)"),
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&nested("(".into(), &self.stmts))
+    }
+}
+
+/// A JSON object for an AST node, with a `kind` and a position
+fn node_json(kind: &str, pos: Pos, fields: &[(&str, String)]) -> String {
+    let mut all_fields = vec![
+        ("kind", json::quote(kind)),
+        ("line", pos.line.to_string()),
+        ("column", pos.column.to_string()),
+    ];
+    all_fields.extend_from_slice(fields);
+    json::object(&all_fields)
+}
+
+fn optional_json(expr: &Option<Expr>) -> String {
+    match expr {
+        Some(e) => e.to_json(),
+        None => "null".into(),
+    }
+}
+
+fn stmts_json(stmts: &[Stmt]) -> String {
+    json::array(stmts.iter().map(Stmt::to_json))
+}
+
+impl Literal {
+    fn to_json(&self) -> (&'static str, String) {
+        match self {
+            Self::Number(x) => ("number", format!("{:?}", x)),
+            Self::String(s) => ("string", json::quote(s)),
+            Self::True => ("bool", "true".into()),
+            Self::False => ("bool", "false".into()),
+            Self::Nil => ("nil", "null".into()),
+        }
+    }
+}
+
+impl Function {
+    fn to_json(&self, kind: &str) -> String {
+        node_json(
+            kind,
+            self.pos,
+            &[
+                ("name", json::quote(self.name.as_str())),
+                (
+                    "params",
+                    json::array(self.params.iter().map(|p| json::quote(p.as_str()))),
+                ),
+                ("body", stmts_json(&self.body)),
+            ],
+        )
+    }
+}
+
+impl Expr {
+    /// A JSON tree of the expression, for tools
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Literal(pos, literal) => {
+                let (type_name, value) = literal.to_json();
+                node_json(
+                    "literal",
+                    *pos,
+                    &[("type", json::quote(type_name)), ("value", value)],
+                )
+            }
+            Self::Variable(pos, variable) => node_json(
+                "variable",
+                *pos,
+                &[("name", json::quote(variable.name.as_str()))],
+            ),
+            Self::Unary(pos, unary) => node_json(
+                "unary",
+                *pos,
+                &[
+                    ("op", json::quote(&unary.op.to_string())),
+                    ("expr", unary.expr.to_json()),
+                ],
+            ),
+            Self::Binary(pos, binary) => node_json(
+                "binary",
+                *pos,
+                &[
+                    ("op", json::quote(&binary.op.to_string())),
+                    ("left", binary.left.to_json()),
+                    ("right", binary.right.to_json()),
+                ],
+            ),
+            Self::Logical(pos, logical) => node_json(
+                "logical",
+                *pos,
+                &[
+                    ("op", json::quote(&logical.op.to_string())),
+                    ("left", logical.left.to_json()),
+                    ("right", logical.right.to_json()),
+                ],
+            ),
+            Self::Grouping(pos, grouping) => {
+                node_json("grouping", *pos, &[("expr", grouping.0.to_json())])
+            }
+            Self::Assign(pos, assign) => node_json(
+                "assign",
+                *pos,
+                &[
+                    ("name", json::quote(assign.target.name.as_str())),
+                    ("rhs", assign.rhs.to_json()),
+                ],
+            ),
+            Self::Call(pos, call) => node_json(
+                "call",
+                *pos,
+                &[
+                    ("callee", call.callee.to_json()),
+                    ("args", json::array(call.args.iter().map(Expr::to_json))),
+                ],
+            ),
+            Self::Ternary(pos, ternary) => node_json(
+                "ternary",
+                *pos,
+                &[
+                    ("condition", ternary.condition.to_json()),
+                    ("then", ternary.then_branch.to_json()),
+                    ("else", ternary.else_branch.to_json()),
+                ],
+            ),
+            Self::Sequence(pos, sequence) => node_json(
+                "sequence",
+                *pos,
+                &[("exprs", json::array(sequence.0.iter().map(Expr::to_json)))],
+            ),
+            Self::Array(pos, array) => node_json(
+                "array",
+                *pos,
+                &[("items", json::array(array.0.iter().map(Expr::to_json)))],
+            ),
+            Self::Map(pos, map) => {
+                let entries = map.0.iter().map(|(key, value)| {
+                    json::object(&[("key", key.to_json()), ("value", value.to_json())])
+                });
+                node_json("map", *pos, &[("entries", json::array(entries))])
+            }
+            Self::Get(pos, get) => node_json(
+                "get",
+                *pos,
+                &[
+                    ("object", get.object.to_json()),
+                    ("name", json::quote(get.name.as_str())),
+                ],
+            ),
+            Self::Set(pos, set) => {
+                let op = match &set.op {
+                    Some(op) => json::quote(&op.to_string()),
+                    None => "null".into(),
+                };
+                node_json(
+                    "set",
+                    *pos,
+                    &[
+                        ("object", set.object.to_json()),
+                        ("name", json::quote(set.name.as_str())),
+                        ("op", op),
+                        ("rhs", set.rhs.to_json()),
+                    ],
+                )
+            }
+            Self::This(pos, _) => node_json("this", *pos, &[]),
+            Self::Super(pos, sup) => node_json(
+                "super",
+                *pos,
+                &[("method", json::quote(sup.method.as_str()))],
+            ),
+            Self::Index(pos, index) => node_json(
+                "index",
+                *pos,
+                &[
+                    ("object", index.object.to_json()),
+                    ("index", index.index.to_json()),
+                ],
+            ),
+            Self::IndexAssign(pos, index_assign) => {
+                let op = match &index_assign.op {
+                    Some(op) => json::quote(&op.to_string()),
+                    None => "null".into(),
+                };
+                node_json(
+                    "index-assign",
+                    *pos,
+                    &[
+                        ("object", index_assign.object.to_json()),
+                        ("index", index_assign.index.to_json()),
+                        ("op", op),
+                        ("rhs", index_assign.rhs.to_json()),
+                    ],
+                )
+            }
+            Self::Lambda(_, function) => function.to_json("lambda"),
+            Self::Increment(pos, increment) => node_json(
+                "increment",
+                *pos,
+                &[
+                    ("name", json::quote(increment.target.name.as_str())),
+                    ("delta", format!("{:?}", increment.delta)),
+                ],
+            ),
+        }
+    }
+}
+
+impl Stmt {
+    /// A JSON tree of the statement, for tools
+    pub fn to_json(&self) -> String {
+        match self {
+            Stmt::Expr(e) => json::object(&[("kind", json::quote("expr")), ("expr", e.to_json())]),
+            Stmt::Function(function) => function.to_json("fun"),
+            Stmt::Class(class) => node_json(
+                "class",
+                class.pos,
+                &[
+                    ("name", json::quote(class.name.as_str())),
+                    ("superclass", optional_json(&class.superclass)),
+                    (
+                        "methods",
+                        json::array(class.methods.iter().map(|m| m.to_json("method"))),
+                    ),
+                ],
+            ),
+            Stmt::Print(exprs) => json::object(&[
+                ("kind", json::quote("print")),
+                ("exprs", json::array(exprs.iter().map(Expr::to_json))),
+            ]),
+            Stmt::Return(e) => {
+                json::object(&[("kind", json::quote("return")), ("expr", optional_json(e))])
+            }
+            Stmt::Var {
+                pos,
+                name,
+                initializer,
+                is_const,
+            } => node_json(
+                if *is_const { "const" } else { "var" },
+                *pos,
+                &[
+                    ("name", json::quote(name.as_str())),
+                    ("initializer", optional_json(initializer)),
+                ],
+            ),
             Stmt::Block(stmts) => {
-                writeln!(f, "(block")?;
-                for stmt in stmts {
-                    writeln!(f, "{}", indent(format!("{}", stmt)))?;
+                json::object(&[("kind", json::quote("block")), ("stmts", stmts_json(stmts))])
+            }
+            Stmt::IfStmt {
+                condition,
+                then_branch,
+                else_branch,
+            } => json::object(&[
+                ("kind", json::quote("if")),
+                ("condition", condition.to_json()),
+                ("then", then_branch.to_json()),
+                (
+                    "else",
+                    match else_branch {
+                        Some(stmt) => stmt.to_json(),
+                        None => "null".into(),
+                    },
+                ),
+            ]),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => json::object(&[
+                ("kind", json::quote("while")),
+                ("condition", condition.to_json()),
+                ("body", body.to_json()),
+                ("increment", optional_json(increment)),
+            ]),
+            Stmt::DoWhile { body, condition } => json::object(&[
+                ("kind", json::quote("do-while")),
+                ("body", body.to_json()),
+                ("condition", condition.to_json()),
+            ]),
+            Stmt::Repeat { count, body } => json::object(&[
+                ("kind", json::quote("repeat")),
+                ("count", count.to_json()),
+                ("body", body.to_json()),
+            ]),
+            Stmt::ForEach {
+                pos,
+                var,
+                iterable,
+                body,
+            } => node_json(
+                "foreach",
+                *pos,
+                &[
+                    ("var", json::quote(var.as_str())),
+                    ("iterable", iterable.to_json()),
+                    ("body", body.to_json()),
+                ],
+            ),
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                let cases = cases.iter().map(|(value, stmts)| {
+                    json::object(&[("value", value.to_json()), ("body", stmts_json(stmts))])
+                });
+                json::object(&[
+                    ("kind", json::quote("switch")),
+                    ("subject", subject.to_json()),
+                    ("cases", json::array(cases)),
+                    (
+                        "default",
+                        match default {
+                            Some(stmts) => stmts_json(stmts),
+                            None => "null".into(),
+                        },
+                    ),
+                ])
+            }
+            Stmt::Break => json::object(&[("kind", json::quote("break"))]),
+            Stmt::Empty => json::object(&[("kind", json::quote("empty"))]),
+            Stmt::Continue => json::object(&[("kind", json::quote("continue"))]),
+        }
+    }
+}
+
+impl Program {
+    /// A JSON tree of the program, for tools
+    pub fn to_json(&self) -> String {
+        json::object(&[
+            ("kind", json::quote("program")),
+            ("stmts", stmts_json(&self.stmts)),
+        ])
+    }
+}
+
+/// Builds a Graphviz DOT graph of a tree. Nodes are numbered in the order
+/// they're added, and children are drawn in the order their edges are added.
+#[derive(Default)]
+struct Dot {
+    lines: Vec<String>,
+    n_nodes: usize,
+}
+
+/// Escape a label for a double-quoted DOT string
+fn dot_quote(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+impl Dot {
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.n_nodes;
+        self.n_nodes += 1;
+        self.lines
+            .push(format!("  n{} [label={}];", id, dot_quote(label)));
+        id
+    }
+
+    /// An edge to a child. An empty label is for items of a list.
+    fn edge(&mut self, parent: usize, child: usize, label: &str) {
+        if label.is_empty() {
+            self.lines.push(format!("  n{} -> n{};", parent, child));
+        } else {
+            self.lines.push(format!(
+                "  n{} -> n{} [label={}];",
+                parent,
+                child,
+                dot_quote(label)
+            ));
+        }
+    }
+
+    fn expr(&mut self, parent: usize, expr: &Expr, label: &str) {
+        let child = expr.dot_node(self);
+        self.edge(parent, child, label);
+    }
+
+    fn exprs(&mut self, parent: usize, exprs: &[Expr]) {
+        for e in exprs {
+            self.expr(parent, e, "");
+        }
+    }
+
+    fn stmt(&mut self, parent: usize, stmt: &Stmt, label: &str) {
+        let child = stmt.dot_node(self);
+        self.edge(parent, child, label);
+    }
+
+    fn stmts(&mut self, parent: usize, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(parent, stmt, "");
+        }
+    }
+
+    fn finish(self) -> String {
+        format!(
+            "digraph ast {{\n  node [shape=box];\n{}\n}}",
+            self.lines.join("\n")
+        )
+    }
+}
+
+impl Literal {
+    fn dot_label(&self) -> String {
+        match self {
+            Self::String(s) => json::quote(s),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl Function {
+    fn dot_node(&self, kind: &str, dot: &mut Dot) -> usize {
+        let params: Vec<&str> = self.params.iter().map(|p| p.as_str()).collect();
+        let id = dot.node(&format!("{} {}({})", kind, self.name, params.join(", ")));
+        dot.stmts(id, &self.body);
+        id
+    }
+}
+
+/// The label of an assignment, like `set x =`, or `set x +=` if compound
+fn assign_label(target: &str, op: &Option<BinaryOperator>) -> String {
+    match op {
+        Some(op) => format!("{} {}=", target, op),
+        None => format!("{} =", target),
+    }
+}
+
+impl Expr {
+    /// A Graphviz DOT graph of the expression, for `dot -Tpng`
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::default();
+        self.dot_node(&mut dot);
+        dot.finish()
+    }
+
+    /// Add the node and its subtree, and return the node's id
+    fn dot_node(&self, dot: &mut Dot) -> usize {
+        match self {
+            Self::Literal(_, literal) => dot.node(&format!("literal {}", literal.dot_label())),
+            Self::Variable(_, variable) => dot.node(&format!("variable {}", variable.name)),
+            Self::Unary(_, unary) => {
+                let id = dot.node(&format!("unary {}", unary.op));
+                dot.expr(id, &unary.expr, "");
+                id
+            }
+            Self::Binary(_, binary) => {
+                let id = dot.node(&format!("binary {}", binary.op));
+                dot.expr(id, &binary.left, "left");
+                dot.expr(id, &binary.right, "right");
+                id
+            }
+            Self::Logical(_, logical) => {
+                let id = dot.node(&format!("logical {}", logical.op));
+                dot.expr(id, &logical.left, "left");
+                dot.expr(id, &logical.right, "right");
+                id
+            }
+            Self::Grouping(_, grouping) => {
+                let id = dot.node("grouping");
+                dot.expr(id, &grouping.0, "");
+                id
+            }
+            Self::Assign(_, assign) => {
+                let id = dot.node(&format!("assign {}", assign.target.name));
+                dot.expr(id, &assign.rhs, "");
+                id
+            }
+            Self::Call(_, call) => {
+                let id = dot.node("call");
+                dot.expr(id, &call.callee, "callee");
+                for (i, arg) in call.args.iter().enumerate() {
+                    dot.expr(id, arg, &format!("arg {}", i));
                 }
-                writeln!(f, ")")
+                id
+            }
+            Self::Ternary(_, ternary) => {
+                let id = dot.node("ternary");
+                dot.expr(id, &ternary.condition, "condition");
+                dot.expr(id, &ternary.then_branch, "then");
+                dot.expr(id, &ternary.else_branch, "else");
+                id
+            }
+            Self::Sequence(_, sequence) => {
+                let id = dot.node("sequence");
+                dot.exprs(id, &sequence.0);
+                id
+            }
+            Self::Array(_, array) => {
+                let id = dot.node("array");
+                dot.exprs(id, &array.0);
+                id
+            }
+            Self::Map(_, map) => {
+                let id = dot.node("map");
+                for (key, value) in &map.0 {
+                    let entry = dot.node("entry");
+                    dot.edge(id, entry, "");
+                    dot.expr(entry, key, "key");
+                    dot.expr(entry, value, "value");
+                }
+                id
+            }
+            Self::Get(_, get) => {
+                let id = dot.node(&format!("get {}", get.name));
+                dot.expr(id, &get.object, "object");
+                id
+            }
+            Self::Set(_, set) => {
+                let id = dot.node(&assign_label(&format!("set {}", set.name), &set.op));
+                dot.expr(id, &set.object, "object");
+                dot.expr(id, &set.rhs, "rhs");
+                id
+            }
+            Self::This(_, _) => dot.node("this"),
+            Self::Super(_, sup) => dot.node(&format!("super {}", sup.method)),
+            Self::Index(_, index) => {
+                let id = dot.node("index");
+                dot.expr(id, &index.object, "object");
+                dot.expr(id, &index.index, "index");
+                id
+            }
+            Self::IndexAssign(_, index_assign) => {
+                let id = dot.node(&assign_label("index-assign", &index_assign.op));
+                dot.expr(id, &index_assign.object, "object");
+                dot.expr(id, &index_assign.index, "index");
+                dot.expr(id, &index_assign.rhs, "rhs");
+                id
+            }
+            Self::Lambda(_, function) => function.dot_node("lambda", dot),
+            Self::Increment(_, increment) => {
+                let op = if increment.delta > 0.0 { "++" } else { "--" };
+                dot.node(&format!("increment {}{}", increment.target.name, op))
+            }
+        }
+    }
+}
+
+impl Stmt {
+    /// Add the node and its subtree, and return the node's id
+    fn dot_node(&self, dot: &mut Dot) -> usize {
+        match self {
+            Stmt::Expr(e) => {
+                let id = dot.node("expr");
+                dot.expr(id, e, "");
+                id
+            }
+            Stmt::Function(function) => function.dot_node("fun", dot),
+            Stmt::Class(class) => {
+                let id = dot.node(&format!("class {}", class.name));
+                if let Some(superclass) = &class.superclass {
+                    dot.expr(id, superclass, "superclass");
+                }
+                for method in &class.methods {
+                    let child = method.dot_node("method", dot);
+                    dot.edge(id, child, "");
+                }
+                id
+            }
+            Stmt::Print(exprs) => {
+                let id = dot.node("print");
+                dot.exprs(id, exprs);
+                id
+            }
+            Stmt::Return(e) => {
+                let id = dot.node("return");
+                if let Some(e) = e {
+                    dot.expr(id, e, "");
+                }
+                id
+            }
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+                ..
+            } => {
+                let keyword = if *is_const { "const" } else { "var" };
+                let id = dot.node(&format!("{} {}", keyword, name));
+                if let Some(e) = initializer {
+                    dot.expr(id, e, "");
+                }
+                id
+            }
+            Stmt::Block(stmts) => {
+                let id = dot.node("block");
+                dot.stmts(id, stmts);
+                id
             }
             Stmt::IfStmt {
                 condition,
                 then_branch,
                 else_branch,
             } => {
+                let id = dot.node("if");
+                dot.expr(id, condition, "condition");
+                dot.stmt(id, then_branch, "then");
                 if let Some(else_branch) = else_branch {
-                    writeln!(f, "(if {} {} {})", condition, then_branch, else_branch)
-                } else {
-                    writeln!(f, "(if {} {})", condition, then_branch)
+                    dot.stmt(id, else_branch, "else");
                 }
+                id
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                let id = dot.node("while");
+                dot.expr(id, condition, "condition");
+                dot.stmt(id, body, "body");
+                if let Some(increment) = increment {
+                    dot.expr(id, increment, "increment");
+                }
+                id
+            }
+            Stmt::DoWhile { body, condition } => {
+                let id = dot.node("do-while");
+                dot.stmt(id, body, "body");
+                dot.expr(id, condition, "condition");
+                id
+            }
+            Stmt::Repeat { count, body } => {
+                let id = dot.node("repeat");
+                dot.expr(id, count, "count");
+                dot.stmt(id, body, "body");
+                id
+            }
+            Stmt::ForEach {
+                var,
+                iterable,
+                body,
+                ..
+            } => {
+                let id = dot.node(&format!("foreach {}", var));
+                dot.expr(id, iterable, "iterable");
+                dot.stmt(id, body, "body");
+                id
             }
-            Stmt::While { condition, body } => {
-                writeln!(f, "(while {} {})", condition, body)
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                let id = dot.node("switch");
+                dot.expr(id, subject, "subject");
+                for (value, stmts) in cases {
+                    let case = dot.node("case");
+                    dot.edge(id, case, "");
+                    dot.expr(case, value, "value");
+                    dot.stmts(case, stmts);
+                }
+                if let Some(stmts) = default {
+                    let case = dot.node("default");
+                    dot.edge(id, case, "");
+                    dot.stmts(case, stmts);
+                }
+                id
             }
+            Stmt::Break => dot.node("break"),
+            Stmt::Empty => dot.node("empty"),
+            Stmt::Continue => dot.node("continue"),
         }
     }
 }
 
-impl fmt::Display for Program {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "(")?;
-        for stmt in &self.stmts {
-            writeln!(f, "{}", indent(format!("{}", stmt)))?;
-        }
-        writeln!(f, ")")
+impl Program {
+    /// A Graphviz DOT graph of the program, for `dot -Tpng`
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::default();
+        let id = dot.node("program");
+        dot.stmts(id, &self.stmts);
+        dot.finish()
     }
 }
 
-struct Parser {
-    tokens: Vec<Token>,
+/// The most parameters a function may have, as in the reference Lox
+const MAX_PARAMS: usize = 255;
+
+/// The most that blocks, statements and expressions may nest, so that a
+/// pathological program is an error rather than a stack overflow
+pub const MAX_NESTING: usize = 200;
+
+struct Parser<'a> {
+    tokens: &'a [Token],
     current: usize,
+    /// How many function bodies we're currently inside
+    function_depth: usize,
+    /// How many loop bodies we're currently inside, within the current function
+    loop_depth: usize,
+    /// How many switch bodies we're currently inside, within the current function
+    switch_depth: usize,
+    /// How deep the node being parsed is, roughly, to enforce `MAX_NESTING`
+    nesting: usize,
+    /// For each class body we're currently inside, whether it has a superclass
+    classes: Vec<bool>,
+    /// Whether the current function is an `init` method
+    in_initializer: bool,
+    /// In the REPL, the last expression statement doesn't need a `;`
+    is_repl: bool,
+    /// Whether `a < b < c` parses as `(a < b) < c`, as in the book, rather than
+    /// being an error. The single-expression commands keep the book's grammar.
+    allow_chained_comparisons: bool,
+    /// Errors we recovered from
+    errors: Vec<ParseError>,
 }
 
-pub struct ParseError();
+/// A syntax error
+pub struct ParseError {
+    pub pos: Pos,
+    /// The lexeme of the offending token, or None if it's the end of the file
+    pub lexeme: Option<String>,
+    pub msg: String,
+}
+
+impl ParseError {
+    /// Whether the error is at the end of the input, so more input may fix it
+    pub fn is_at_end(&self) -> bool {
+        self.lexeme.is_none()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let where_s = match &self.lexeme {
+            Some(lexeme) => color::lexeme(format!("'{}'", lexeme)),
+            None => "end".into(),
+        };
+        let pos = color::pos(format!("[{}]", self.pos));
+        write!(f, "{} Error at {}: {}", pos, where_s, self.msg)
+    }
+}
 
-impl Parser {
-    fn new(tokens: &[Token]) -> Self {
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
         Parser {
-            tokens: tokens.to_vec(),
+            tokens,
             current: 0,
+            function_depth: 0,
+            loop_depth: 0,
+            switch_depth: 0,
+            nesting: 0,
+            classes: Vec::new(),
+            in_initializer: false,
+            is_repl: false,
+            allow_chained_comparisons: false,
+            errors: Vec::new(),
         }
     }
 
-    fn previous(&self) -> &Token {
+    fn previous(&self) -> &'a Token {
         assert!(self.current > 0);
         &self.tokens[self.current - 1]
     }
 
-    fn peek(&self) -> &Token {
+    fn peek(&self) -> &'a Token {
         &self.tokens[self.current]
     }
 
@@ -266,6 +1477,11 @@ impl Parser {
         self.peek().token_type == token_type
     }
 
+    /// Check the token following the next one
+    fn check_next(&self, token_type: TokenType) -> bool {
+        !self.is_at_end() && self.tokens[self.current + 1].token_type == token_type
+    }
+
     fn is_at_end(&self) -> bool {
         self.peek().token_type == TokenType::Eof
     }
@@ -299,43 +1515,228 @@ impl Parser {
     }
 
     fn error(&self, token: &Token, msg: &str) -> ParseError {
-        let where_s: String = if token.token_type == TokenType::Eof {
-            "end".into()
-        } else {
-            format!("'{}'", token.lexeme)
-        };
-        eprintln!("[line {}] Error at {}: {}", token.line, where_s, msg);
-        ParseError()
+        ParseError {
+            pos: token.pos(),
+            lexeme: (token.token_type != TokenType::Eof).then(|| token.lexeme.to_string()),
+            msg: msg.into(),
+        }
+    }
+
+    fn pos(&self) -> Pos {
+        self.peek().pos()
     }
 
-    fn line(&self) -> usize {
-        self.peek().line
+    /// Go one level of nesting deeper, failing if it's too deep
+    fn nest(&mut self) -> Result<(), ParseError> {
+        if self.nesting >= MAX_NESTING {
+            return Err(self.error(self.peek(), "Too deeply nested."));
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+
+    /// Run `parse` one level of nesting deeper
+    fn nested<T>(
+        &mut self,
+        parse: fn(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        self.nest()?;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
     }
 
-    fn program(&mut self) -> Result<Program, ParseError> {
+    fn program(&mut self) -> Result<Program, Vec<ParseError>> {
         let mut stmts = Vec::<Stmt>::new();
         while !self.is_at_end() {
-            stmts.push(self.declaration()?);
+            if let Some(stmt) = self.declaration_or_recover() {
+                stmts.push(stmt);
+            }
+        }
+        if self.errors.is_empty() {
+            Ok(Program { stmts })
+        } else {
+            Err(mem::take(&mut self.errors))
+        }
+    }
+
+    /// Parse a declaration. On error, record it and skip to what looks like the
+    /// start of the next statement, so we can go on and report more errors.
+    fn declaration_or_recover(&mut self) -> Option<Stmt> {
+        let nesting = self.nesting;
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                // The error may have skipped leaving some levels
+                self.nesting = nesting;
+                self.errors.push(err);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn synchronize(&mut self) {
+        while !self.is_at_end() {
+            self.current += 1;
+            if self.previous().token_type == TokenType::Semicolon {
+                return;
+            }
+            match self.peek().token_type {
+                TokenType::Class
+                | TokenType::Const
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Do
+                | TokenType::Repeat
+                | TokenType::Foreach
+                | TokenType::Switch
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => {}
+            }
         }
-        Ok(Program { stmts })
     }
 
     fn declaration(&mut self) -> Result<Stmt, ParseError> {
         if self.check_advance(TokenType::Var) {
             self.consume(TokenType::Identifier, "Expecting var name")?;
-            let name = self.previous().lexeme.clone();
+            let pos = self.previous().pos();
+            let name = self.previous().name();
             let initializer = if self.check_advance(TokenType::Equal) {
                 Some(self.expression()?)
             } else {
                 None
             };
             self.consume(TokenType::Semicolon, "Expecting `;`")?;
-            Ok(Stmt::Var { name, initializer })
+            Ok(Stmt::Var {
+                pos,
+                name,
+                initializer,
+                is_const: false,
+            })
+        } else if self.check_advance(TokenType::Const) {
+            self.consume(TokenType::Identifier, "Expecting const name")?;
+            let pos = self.previous().pos();
+            let name = self.previous().name();
+            self.consume(
+                TokenType::Equal,
+                "Expecting '=', since a const needs a value",
+            )?;
+            let initializer = self.expression()?;
+            self.consume(TokenType::Semicolon, "Expecting `;`")?;
+            Ok(Stmt::Var {
+                pos,
+                name,
+                initializer: Some(initializer),
+                is_const: true,
+            })
+        } else if self.check_advance(TokenType::Class) {
+            self.class()
+        } else if self.check(TokenType::Fun) && self.check_next(TokenType::Identifier) {
+            // Otherwise, it's a statement starting with a lambda expression
+            self.current += 1;
+            self.function()
         } else {
             self.stmt()
         }
     }
 
+    fn function(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::Identifier, "Expecting function name")?;
+        let pos = self.previous().pos();
+        let name = self.previous().name();
+        let function = self.function_rest(pos, name, false)?;
+        Ok(Stmt::Function(Rc::new(function)))
+    }
+
+    fn class(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::Identifier, "Expecting class name")?;
+        let pos = self.previous().pos();
+        let name = self.previous().name();
+        let superclass = if self.check_advance(TokenType::Less) {
+            self.consume(TokenType::Identifier, "Expecting superclass name")?;
+            let token = self.previous();
+            if token.name() == name {
+                return Err(self.error(token, "A class can't inherit from itself."));
+            }
+            Some(Expr::Variable(token.pos(), Variable::new(token.name())))
+        } else {
+            None
+        };
+        self.consume(TokenType::LeftBrace, "Expecting '{'")?;
+        self.classes.push(superclass.is_some());
+        let methods = self.methods();
+        self.classes.pop();
+        Ok(Stmt::Class(Rc::new(Class {
+            pos,
+            name,
+            superclass,
+            methods: methods?,
+        })))
+    }
+
+    /// Parse methods up to the `}` which closes the class body
+    fn methods(&mut self) -> Result<Vec<Rc<Function>>, ParseError> {
+        let mut methods = Vec::<Rc<Function>>::new();
+        while !self.check_advance(TokenType::RightBrace) {
+            self.consume(TokenType::Identifier, "Expecting method name")?;
+            let pos = self.previous().pos();
+            let name = self.previous().name();
+            let is_initializer = name.as_str() == "init";
+            methods.push(Rc::new(self.function_rest(pos, name, is_initializer)?));
+        }
+        Ok(methods)
+    }
+
+    /// Parse the parameters and the body of a function, which follow its name
+    fn function_rest(
+        &mut self,
+        pos: Pos,
+        name: Symbol,
+        is_initializer: bool,
+    ) -> Result<Function, ParseError> {
+        self.consume(TokenType::LeftParen, "Expecting '('")?;
+        let mut params = Vec::<Symbol>::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.consume(TokenType::Identifier, "Expecting parameter name")?;
+                let param = self.previous();
+                if params.len() == MAX_PARAMS {
+                    return Err(self.error(param, "Can't have more than 255 parameters."));
+                }
+                if params.contains(&param.name()) {
+                    return Err(self.error(param, "Already a parameter with this name."));
+                }
+                params.push(param.name());
+                if !self.check_advance(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expecting ')'")?;
+        self.consume(TokenType::LeftBrace, "Expecting '{'")?;
+        self.function_depth += 1;
+        let loop_depth = mem::replace(&mut self.loop_depth, 0);
+        let switch_depth = mem::replace(&mut self.switch_depth, 0);
+        let in_initializer = mem::replace(&mut self.in_initializer, is_initializer);
+        let body = self.nested(Self::block);
+        self.in_initializer = in_initializer;
+        self.loop_depth = loop_depth;
+        self.switch_depth = switch_depth;
+        self.function_depth -= 1;
+        let body = body?;
+        Ok(Function {
+            pos,
+            name,
+            params,
+            body,
+        })
+    }
+
     fn stmt(&mut self) -> Result<Stmt, ParseError> {
         if self.check_advance(TokenType::Print) {
             self.print_statement()
@@ -345,34 +1746,114 @@ impl Parser {
             self.if_statement()
         } else if self.check_advance(TokenType::While) {
             self.while_statement()
+        } else if self.check_advance(TokenType::Do) {
+            self.do_while_statement()
+        } else if self.check_advance(TokenType::Repeat) {
+            self.repeat_statement()
         } else if self.check_advance(TokenType::For) {
             self.for_statement()
+        } else if self.check_advance(TokenType::Foreach) {
+            self.foreach_statement()
+        } else if self.check_advance(TokenType::Switch) {
+            self.switch_statement()
+        } else if self.check_advance(TokenType::Return) {
+            self.return_statement()
+        } else if self.check_advance(TokenType::Break) {
+            self.loop_jump_statement(Stmt::Break)
+        } else if self.check_advance(TokenType::Continue) {
+            self.loop_jump_statement(Stmt::Continue)
+        } else if self.check_advance(TokenType::Semicolon) {
+            Ok(Stmt::Empty)
         } else {
             self.expression_statement()
         }
     }
 
     fn print_statement(&mut self) -> Result<Stmt, ParseError> {
-        let expr = self.expression()?;
+        let mut exprs = vec![self.assignment()?];
+        while self.check_advance(TokenType::Comma) {
+            exprs.push(self.assignment()?);
+        }
         self.consume(TokenType::Semicolon, "Expecting `;`")?;
-        Ok(Stmt::Print(expr))
+        Ok(Stmt::Print(exprs))
+    }
+
+    /// Parse declarations until the closing `}`. The `{` should already be eaten.
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut stmts = Vec::<Stmt>::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            if let Some(stmt) = self.declaration_or_recover() {
+                stmts.push(stmt);
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expecting '}'")?;
+        Ok(stmts)
     }
 
     fn block_statement(&mut self) -> Result<Stmt, ParseError> {
+        Ok(Stmt::Block(self.nested(Self::block)?))
+    }
+
+    fn switch_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.switch_depth += 1;
+        let stmt = self.nested(Self::switch_rest);
+        self.switch_depth -= 1;
+        stmt
+    }
+
+    /// Parse a switch after the `switch` keyword
+    fn switch_rest(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::LeftParen, "Expecting '('")?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, "Expecting ')'")?;
+        self.consume(TokenType::LeftBrace, "Expecting '{'")?;
+        let mut cases = Vec::<(Expr, Vec<Stmt>)>::new();
+        let mut default = None;
+        loop {
+            if self.check_advance(TokenType::Case) {
+                let value = self.expression()?;
+                self.consume(TokenType::Colon, "Expecting ':'")?;
+                cases.push((value, self.case_body()));
+            } else if self.check_advance(TokenType::Default) {
+                if default.is_some() {
+                    return Err(self.error(self.previous(), "A switch can only have one default."));
+                }
+                self.consume(TokenType::Colon, "Expecting ':'")?;
+                default = Some(self.case_body());
+            } else {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expecting '}'")?;
+        Ok(Stmt::Switch {
+            subject,
+            cases,
+            default,
+        })
+    }
+
+    /// Parse declarations up to the next `case`, `default` or the closing `}`
+    fn case_body(&mut self) -> Vec<Stmt> {
         let mut stmts = Vec::<Stmt>::new();
-        while !self.check_advance(TokenType::RightBrace) {
-            stmts.push(self.declaration()?);
+        while !self.check(TokenType::Case)
+            && !self.check(TokenType::Default)
+            && !self.check(TokenType::RightBrace)
+            && !self.is_at_end()
+        {
+            if let Some(stmt) = self.declaration_or_recover() {
+                stmts.push(stmt);
+            }
         }
-        Ok(Stmt::Block(stmts))
+        stmts
     }
 
     fn if_statement(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expecting '('")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expecting ')'")?;
-        let then_branch = Box::new(self.stmt()?);
+        let then_branch = Box::new(self.nested(Self::stmt)?);
         let else_branch = if self.check_advance(TokenType::Else) {
-            Some(Box::new(self.stmt()?))
+            Some(Box::new(self.nested(Self::stmt)?))
         } else {
             None
         };
@@ -387,12 +1868,34 @@ impl Parser {
         self.consume(TokenType::LeftParen, "Expecting '('")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expecting ')'")?;
-        let body = Box::new(self.stmt()?);
-        Ok(Stmt::While { condition, body })
+        let body = self.loop_body()?;
+        Ok(Stmt::While {
+            condition,
+            body,
+            increment: None,
+        })
+    }
+
+    fn do_while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let body = self.loop_body()?;
+        self.consume(TokenType::While, "Expecting 'while'")?;
+        self.consume(TokenType::LeftParen, "Expecting '('")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expecting ')'")?;
+        self.consume(TokenType::Semicolon, "Expecting `;`")?;
+        Ok(Stmt::DoWhile { body, condition })
+    }
+
+    fn repeat_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::LeftParen, "Expecting '('")?;
+        let count = self.expression()?;
+        self.consume(TokenType::RightParen, "Expecting ')'")?;
+        let body = self.loop_body()?;
+        Ok(Stmt::Repeat { count, body })
     }
 
     fn for_statement(&mut self) -> Result<Stmt, ParseError> {
-        let line = self.line();
+        let pos = self.pos();
         self.consume(TokenType::LeftParen, "Expecting '('")?;
         let initializer = if self.check_advance(TokenType::Semicolon) {
             None
@@ -415,20 +1918,18 @@ impl Parser {
             self.consume(TokenType::RightParen, "Expecting ')'")?;
             Some(expr)
         };
-        let mut body = self.stmt()?;
+        let body = self.loop_body()?;
 
-        // Desugar the if into a while
-        if let Some(increment) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expr(increment)]);
-        }
+        // Desugar the for into a while
         let condition = if let Some(condition) = condition {
             condition
         } else {
-            Expr::Literal(line, Literal::True)
+            Expr::Literal(pos, Literal::True)
         };
-        body = Stmt::While {
+        let mut body = Stmt::While {
             condition,
-            body: Box::new(body),
+            body,
+            increment,
         };
         if let Some(initializer) = initializer {
             body = Stmt::Block(vec![initializer, body]);
@@ -437,45 +1938,171 @@ impl Parser {
         Ok(body)
     }
 
+    fn foreach_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::LeftParen, "Expecting '('")?;
+        self.consume(TokenType::Identifier, "Expecting var name")?;
+        let pos = self.previous().pos();
+        let var = self.previous().name();
+        self.consume(TokenType::In, "Expecting 'in'")?;
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "Expecting ')'")?;
+        let body = self.loop_body()?;
+        Ok(Stmt::ForEach {
+            pos,
+            var,
+            iterable,
+            body,
+        })
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        if self.function_depth == 0 {
+            return Err(self.error(self.previous(), "Can't return from top-level code."));
+        }
+        let value = if self.check(TokenType::Semicolon) {
+            None
+        } else {
+            if self.in_initializer {
+                return Err(
+                    self.error(self.previous(), "Can't return a value from an initializer.")
+                );
+            }
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::Semicolon, "Expecting `;`")?;
+        Ok(Stmt::Return(value))
+    }
+
+    /// Finish parsing a `break` or `continue`, whose keyword was already eaten.
+    /// A `break` may also end a switch.
+    fn loop_jump_statement(&mut self, stmt: Stmt) -> Result<Stmt, ParseError> {
+        let in_switch = matches!(stmt, Stmt::Break) && self.switch_depth > 0;
+        if self.loop_depth == 0 && !in_switch {
+            let msg = format!("Can't use '{}' outside of a loop.", self.previous().lexeme);
+            return Err(self.error(self.previous(), &msg));
+        }
+        self.consume(TokenType::Semicolon, "Expecting `;`")?;
+        Ok(stmt)
+    }
+
+    /// Parse a loop body, keeping track that we're inside a loop
+    fn loop_body(&mut self) -> Result<Box<Stmt>, ParseError> {
+        self.loop_depth += 1;
+        let body = self.nested(Self::stmt);
+        self.loop_depth -= 1;
+        Ok(Box::new(body?))
+    }
+
     fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
         let expr = self.expression()?;
+        if self.is_repl && self.is_at_end() {
+            return Ok(Stmt::Expr(expr));
+        }
         self.consume(TokenType::Semicolon, "Expecting `;`")?;
         Ok(Stmt::Expr(expr))
     }
 
+    /// Parse an expression, including the comma operator. Where commas separate
+    /// items, as in arguments, arrays, maps and `print`, each item is parsed
+    /// with `assignment()` instead, so `f(a, b)` has two arguments. A comma
+    /// expression can still be an item if it's in parentheses, like `f((a, b))`.
     fn expression(&mut self) -> Result<Expr, ParseError> {
-        self.assignment()
+        let first = self.assignment()?;
+        if !self.check(TokenType::Comma) {
+            return Ok(first);
+        }
+        let pos = self.peek().pos();
+        let mut exprs = vec![first];
+        while self.check_advance(TokenType::Comma) {
+            exprs.push(self.assignment()?);
+        }
+        Ok(Expr::Sequence(pos, Sequence(exprs)))
     }
 
     fn assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.logic_or()?;
-        if self.check_advance(TokenType::Equal) {
-            let equals = self.previous().clone();
-            let rhs = self.assignment()?;
-            if let Expr::Variable(line, Variable(name)) = expr {
-                Ok(Expr::Assign(
-                    line,
-                    Assign {
-                        name,
-                        rhs: Box::new(rhs),
-                    },
-                ))
-            } else {
-                Err(self.error(&equals, "Invalid assignment target"))
+        let expr = self.nested(Self::ternary)?;
+        // For compound assignment, the operator to apply
+        let op = match self.peek().token_type {
+            TokenType::Equal => None,
+            TokenType::PlusEqual => Some(BinaryOperator::Add),
+            TokenType::MinusEqual => Some(BinaryOperator::Sub),
+            TokenType::StarEqual => Some(BinaryOperator::Mul),
+            TokenType::SlashEqual => Some(BinaryOperator::Div),
+            _ => return Ok(expr),
+        };
+        self.advance()?;
+        let equals = self.previous();
+        let rhs = Box::new(self.nested(Self::assignment)?);
+        match expr {
+            Expr::Variable(pos, target) => {
+                // Desugar `x += e` into `x = x + e`
+                let rhs = match op {
+                    None => rhs,
+                    Some(op) => Box::new(Expr::Binary(
+                        equals.pos(),
+                        Binary {
+                            left: Box::new(Expr::Variable(pos, Variable::new(target.name))),
+                            op,
+                            right: rhs,
+                        },
+                    )),
+                };
+                Ok(Expr::Assign(pos, Assign { target, rhs }))
             }
-        } else {
-            Ok(expr)
+            // This isn't desugared, so the object and index are only evaluated once
+            Expr::Index(pos, Index { object, index }) => Ok(Expr::IndexAssign(
+                pos,
+                IndexAssign {
+                    object,
+                    index,
+                    op,
+                    rhs,
+                },
+            )),
+            Expr::Get(pos, Get { object, name }) => Ok(Expr::Set(
+                pos,
+                Set {
+                    object,
+                    name,
+                    op,
+                    rhs,
+                },
+            )),
+            _ => Err(self.error(equals, "Invalid assignment target")),
+        }
+    }
+
+    fn ternary(&mut self) -> Result<Expr, ParseError> {
+        let condition = self.logic_or()?;
+        if !self.check_advance(TokenType::Question) {
+            return Ok(condition);
         }
+        let pos = self.previous().pos();
+        let then_branch = self.expression()?;
+        self.consume(TokenType::Colon, "Expecting ':'")?;
+        // Recursing makes the operator right-associative
+        let else_branch = self.nested(Self::ternary)?;
+        Ok(Expr::Ternary(
+            pos,
+            Ternary {
+                condition: Box::new(condition),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            },
+        ))
     }
 
     fn logic_or(&mut self) -> Result<Expr, ParseError> {
+        let nesting = self.nesting;
         let mut expr = self.logic_and()?;
 
         loop {
             if self.check_advance(TokenType::Or) {
+                self.nest()?;
+                let pos = self.previous().pos();
                 let right = self.logic_and()?;
                 expr = Expr::Logical(
-                    self.line(),
+                    pos,
                     Logical {
                         left: Box::new(expr),
                         op: LogicalOperator::Or,
@@ -483,19 +2110,23 @@ impl Parser {
                     },
                 );
             } else {
+                self.nesting = nesting;
                 return Ok(expr);
             }
         }
     }
 
     fn logic_and(&mut self) -> Result<Expr, ParseError> {
+        let nesting = self.nesting;
         let mut expr = self.equality()?;
 
         loop {
             if self.check_advance(TokenType::And) {
+                self.nest()?;
+                let pos = self.previous().pos();
                 let right = self.equality()?;
                 expr = Expr::Logical(
-                    self.line(),
+                    pos,
                     Logical {
                         left: Box::new(expr),
                         op: LogicalOperator::And,
@@ -503,24 +2134,30 @@ impl Parser {
                     },
                 );
             } else {
+                self.nesting = nesting;
                 return Ok(expr);
             }
         }
     }
 
-    fn equality(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.comparison()?;
-
-        loop {
-            let op = match self.peek().token_type {
-                TokenType::BangEqual => BinaryOperator::NotEqual,
-                TokenType::EqualEqual => BinaryOperator::Equal,
-                _ => return Ok(expr),
-            };
+    /// Parse a left-associative chain of binary operators. `operand` parses the
+    /// operands, and `operator` gives the operator of a token, if it's one.
+    fn binary_chain(
+        &mut self,
+        operand: fn(&mut Self) -> Result<Expr, ParseError>,
+        operator: fn(TokenType) -> Option<BinaryOperator>,
+    ) -> Result<Expr, ParseError> {
+        let nesting = self.nesting;
+        let mut expr = operand(self)?;
+
+        // Each operator nests the chain so far one level deeper
+        while let Some(op) = operator(self.peek().token_type) {
+            self.nest()?;
             self.advance()?;
-            let right = self.comparison()?;
+            let pos = self.previous().pos();
+            let right = operand(self)?;
             expr = Expr::Binary(
-                self.line(),
+                pos,
                 Binary {
                     left: Box::new(expr),
                     op,
@@ -528,74 +2165,119 @@ impl Parser {
                 },
             );
         }
+        self.nesting = nesting;
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> Result<Expr, ParseError> {
+        self.binary_chain(Self::bitwise_or, |token_type| match token_type {
+            TokenType::BangEqual => Some(BinaryOperator::NotEqual),
+            TokenType::EqualEqual => Some(BinaryOperator::Equal),
+            _ => None,
+        })
+    }
+
+    fn bitwise_or(&mut self) -> Result<Expr, ParseError> {
+        self.binary_chain(Self::bitwise_xor, |token_type| match token_type {
+            TokenType::Pipe => Some(BinaryOperator::BitOr),
+            _ => None,
+        })
+    }
+
+    fn bitwise_xor(&mut self) -> Result<Expr, ParseError> {
+        self.binary_chain(Self::bitwise_and, |token_type| match token_type {
+            TokenType::Caret => Some(BinaryOperator::BitXor),
+            _ => None,
+        })
+    }
+
+    fn bitwise_and(&mut self) -> Result<Expr, ParseError> {
+        self.binary_chain(Self::shift, |token_type| match token_type {
+            TokenType::Ampersand => Some(BinaryOperator::BitAnd),
+            _ => None,
+        })
+    }
+
+    fn shift(&mut self) -> Result<Expr, ParseError> {
+        self.binary_chain(Self::comparison, |token_type| match token_type {
+            TokenType::LessLess => Some(BinaryOperator::ShiftLeft),
+            TokenType::GreaterGreater => Some(BinaryOperator::ShiftRight),
+            _ => None,
+        })
     }
 
+    /// Like `binary_chain`, but `a < b < c` is an error, since it would compare
+    /// a bool with `c`
     fn comparison(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.term()?;
+        let operator = |token_type| match token_type {
+            TokenType::Greater => Some(BinaryOperator::Greater),
+            TokenType::GreaterEqual => Some(BinaryOperator::GreaterEqual),
+            TokenType::Less => Some(BinaryOperator::Less),
+            TokenType::LessEqual => Some(BinaryOperator::LessEqual),
+            _ => None,
+        };
+        if self.allow_chained_comparisons {
+            return self.binary_chain(Self::term, operator);
+        }
+        let start = self.current;
+        let left = self.term()?;
+        let Some(op) = operator(self.peek().token_type) else {
+            return Ok(left);
+        };
+        self.advance()?;
+        let pos = self.previous().pos();
+        let right = self.term()?;
+        if operator(self.peek().token_type).is_some() {
+            let second_op = self.peek();
+            while operator(self.peek().token_type).is_some() {
+                self.advance()?;
+                self.term()?;
+            }
+            let msg = format!(
+                "Chained comparison '{}' is not allowed; use explicit parentheses or 'and'.",
+                self.source_text(start)
+            );
+            return Err(self.error(second_op, &msg));
+        }
+        Ok(Expr::Binary(
+            pos,
+            Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            },
+        ))
+    }
 
-        loop {
-            let op = match self.peek().token_type {
-                TokenType::Greater => BinaryOperator::Greater,
-                TokenType::GreaterEqual => BinaryOperator::GreaterEqual,
-                TokenType::Less => BinaryOperator::Less,
-                TokenType::LessEqual => BinaryOperator::LessEqual,
-                _ => return Ok(expr),
-            };
-            self.advance()?;
-            let right = self.term()?;
-            expr = Expr::Binary(
-                self.line(),
-                Binary {
-                    left: Box::new(expr),
-                    op,
-                    right: Box::new(right),
-                },
-            )
+    /// The source of the tokens from `start` up to the current one, with a
+    /// space wherever there was something between them
+    fn source_text(&self, start: usize) -> String {
+        let mut text = String::new();
+        let tokens = &self.tokens[start..self.current];
+        for (i, token) in tokens.iter().enumerate() {
+            if i > 0 && tokens[i - 1].end < token.start {
+                text.push(' ');
+            }
+            text.push_str(token.lexeme.as_str());
         }
+        text
     }
 
     fn term(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.factor()?;
-
-        loop {
-            let op = match self.peek().token_type {
-                TokenType::Minus => BinaryOperator::Sub,
-                TokenType::Plus => BinaryOperator::Add,
-                _ => return Ok(expr),
-            };
-            self.advance()?;
-            let right = self.factor()?;
-            expr = Expr::Binary(
-                self.line(),
-                Binary {
-                    left: Box::new(expr),
-                    op,
-                    right: Box::new(right),
-                },
-            )
-        }
+        self.binary_chain(Self::factor, |token_type| match token_type {
+            TokenType::Minus => Some(BinaryOperator::Sub),
+            TokenType::Plus => Some(BinaryOperator::Add),
+            _ => None,
+        })
     }
 
     fn factor(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.unary()?;
-
-        loop {
-            let op = match self.peek().token_type {
-                TokenType::Slash => BinaryOperator::Div,
-                TokenType::Star => BinaryOperator::Mul,
-                _ => return Ok(expr),
-            };
-            self.advance()?;
-            let right = self.unary()?;
-            expr = Expr::Binary(
-                self.line(),
-                Binary {
-                    left: Box::new(expr),
-                    op,
-                    right: Box::new(right),
-                },
-            )
-        }
+        self.binary_chain(Self::unary, |token_type| match token_type {
+            TokenType::Slash => Some(BinaryOperator::Div),
+            TokenType::Star => Some(BinaryOperator::Mul),
+            TokenType::Percent => Some(BinaryOperator::Mod),
+            _ => None,
+        })
     }
 
     fn unary(&mut self) -> Result<Expr, ParseError> {
@@ -607,50 +2289,233 @@ impl Parser {
         if let Some(op) = op {
             self.advance()?;
             Ok(Expr::Unary(
-                self.line(),
+                self.previous().pos(),
                 Unary {
                     op,
-                    expr: Box::new(self.unary()?),
+                    expr: Box::new(self.nested(Self::unary)?),
                 },
             ))
         } else {
-            self.primary()
+            self.call()
+        }
+    }
+
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let nesting = self.nesting;
+        let mut expr = self.primary()?;
+
+        loop {
+            if matches!(
+                self.peek().token_type,
+                TokenType::LeftParen | TokenType::LeftBracket | TokenType::Dot
+            ) {
+                self.nest()?;
+            }
+            if self.check_advance(TokenType::LeftParen) {
+                let pos = self.previous().pos();
+                let args = self.expression_list(TokenType::RightParen, "Expecting ')'")?;
+                expr = Expr::Call(
+                    pos,
+                    Call {
+                        callee: Box::new(expr),
+                        args,
+                    },
+                );
+            } else if self.check_advance(TokenType::LeftBracket) {
+                let pos = self.previous().pos();
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket, "Expecting ']'")?;
+                expr = Expr::Index(
+                    pos,
+                    Index {
+                        object: Box::new(expr),
+                        index: Box::new(index),
+                    },
+                );
+            } else if self.check_advance(TokenType::Dot) {
+                let pos = self.previous().pos();
+                self.consume(TokenType::Identifier, "Expecting property name after '.'")?;
+                let name = self.previous().name();
+                expr = Expr::Get(
+                    pos,
+                    Get {
+                        object: Box::new(expr),
+                        name,
+                    },
+                );
+            } else if self.check_advance(TokenType::PlusPlus)
+                || self.check_advance(TokenType::MinusMinus)
+            {
+                let op = self.previous();
+                let Expr::Variable(pos, target) = expr else {
+                    return Err(self.error(op, "Invalid increment target"));
+                };
+                let delta = if op.token_type == TokenType::PlusPlus {
+                    1.0
+                } else {
+                    -1.0
+                };
+                expr = Expr::Increment(pos, Increment { target, delta });
+            } else {
+                self.nesting = nesting;
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Parse comma-separated expressions, up to and including the closing token.
+    /// A trailing comma is allowed after the last expression.
+    fn expression_list(&mut self, closing: TokenType, msg: &str) -> Result<Vec<Expr>, ParseError> {
+        let mut exprs = Vec::<Expr>::new();
+        if !self.check(closing) {
+            loop {
+                exprs.push(self.assignment()?);
+                if !self.check_advance(TokenType::Comma) || self.check(closing) {
+                    break;
+                }
+            }
         }
+        self.consume(closing, msg)?;
+        Ok(exprs)
     }
 
     fn primary(&mut self) -> Result<Expr, ParseError> {
         self.advance()?;
-        let token = self.previous().clone();
+        let token = self.previous();
         let expr = match token.token_type {
-            TokenType::Identifier => Expr::Variable(token.line, Variable(token.lexeme)),
-            TokenType::Number => {
-                let x = token.lexeme.parse::<f64>().unwrap();
-                Expr::Literal(token.line, Literal::Number(x))
-            }
+            TokenType::Identifier => Expr::Variable(token.pos(), Variable::new(token.name())),
+            TokenType::Number => Expr::Literal(token.pos(), Literal::Number(token.number_value())),
             TokenType::StringLiteral => {
-                let s = token.lexeme[1..token.lexeme.len() - 1].to_string();
-                Expr::Literal(token.line, Literal::String(s))
+                Expr::Literal(token.pos(), Literal::String(token.string_value()))
+            }
+            TokenType::True => Expr::Literal(token.pos(), Literal::True),
+            TokenType::False => Expr::Literal(token.pos(), Literal::False),
+            TokenType::Nil => Expr::Literal(token.pos(), Literal::Nil),
+            TokenType::This => {
+                if self.classes.is_empty() {
+                    return Err(self.error(token, "Can't use 'this' outside of a class."));
+                }
+                Expr::This(token.pos(), Variable::new(Symbol::this()))
+            }
+            TokenType::Super => {
+                match self.classes.last() {
+                    None => return Err(self.error(token, "Can't use 'super' outside of a class.")),
+                    Some(false) => {
+                        return Err(
+                            self.error(token, "Can't use 'super' in a class with no superclass.")
+                        )
+                    }
+                    Some(true) => {}
+                }
+                self.consume(TokenType::Dot, "Expecting '.' after 'super'")?;
+                self.consume(TokenType::Identifier, "Expecting superclass method name")?;
+                let method = self.previous().name();
+                let superclass = Variable::new(Symbol::super_());
+                Expr::Super(token.pos(), Super { superclass, method })
             }
-            TokenType::True => Expr::Literal(token.line, Literal::True),
-            TokenType::False => Expr::Literal(token.line, Literal::False),
-            TokenType::Nil => Expr::Literal(token.line, Literal::Nil),
             TokenType::LeftParen => {
                 let expr = self.expression()?;
-                self.consume(TokenType::RightParen, "Expecting `)`")?;
-                Expr::Grouping(token.line, Grouping(Box::new(expr)))
+                // The group may span lines, so point back to where it started
+                let msg = format!("Expecting `)` to close the `(` at {}", token.pos());
+                self.consume(TokenType::RightParen, &msg)?;
+                Expr::Grouping(token.pos(), Grouping(Box::new(expr)))
+            }
+            TokenType::LeftBracket => {
+                let items = self.expression_list(TokenType::RightBracket, "Expecting ']'")?;
+                Expr::Array(token.pos(), Array(items))
+            }
+            // A `{` which starts a statement is a block. Anywhere else, it's a map.
+            TokenType::LeftBrace => {
+                let mut entries = Vec::<(Expr, Expr)>::new();
+                if !self.check(TokenType::RightBrace) {
+                    loop {
+                        let key = self.assignment()?;
+                        self.consume(TokenType::Colon, "Expecting ':'")?;
+                        entries.push((key, self.assignment()?));
+                        if !self.check_advance(TokenType::Comma)
+                            || self.check(TokenType::RightBrace)
+                        {
+                            break;
+                        }
+                    }
+                }
+                self.consume(TokenType::RightBrace, "Expecting '}'")?;
+                Expr::Map(token.pos(), Map(entries))
+            }
+            TokenType::Fun => {
+                let function = self.function_rest(token.pos(), Symbol::intern(""), false)?;
+                Expr::Lambda(token.pos(), Rc::new(function))
+            }
+            _ => {
+                // Leave the token in place, so error recovery starts from it
+                self.current -= 1;
+                return Err(self.error(token, "Unexpected token"));
             }
-            _ => return Err(self.error(&token, "Unexpected token")),
         };
         Ok(expr)
     }
 }
 
+/// Parse a single expression, which may be followed by a `;`
 pub fn parse_expr(tokens: &[Token]) -> Result<Expr, ParseError> {
     let mut parser = Parser::new(tokens);
-    parser.expression()
+    parser.allow_chained_comparisons = true;
+    let expr = parser.expression()?;
+    parser.check_advance(TokenType::Semicolon);
+    if !parser.is_at_end() {
+        return Err(parser.error(parser.peek(), "Expect end of expression."));
+    }
+    Ok(expr)
+}
+
+/// Whether the tokens look like a program rather than a single expression:
+/// they start with a statement keyword, or have a `;` which isn't the last
+/// token and isn't inside braces.
+pub fn looks_like_program(tokens: &[Token]) -> bool {
+    let starts_with_statement = match tokens.first().map(|t| t.token_type) {
+        Some(
+            TokenType::Var
+            | TokenType::Const
+            | TokenType::Class
+            | TokenType::Print
+            | TokenType::If
+            | TokenType::While
+            | TokenType::Do
+            | TokenType::Repeat
+            | TokenType::For
+            | TokenType::Foreach
+            | TokenType::Switch
+            | TokenType::Return
+            | TokenType::Break
+            | TokenType::Continue,
+        ) => true,
+        Some(TokenType::Fun) => tokens.get(1).map(|t| t.token_type) == Some(TokenType::Identifier),
+        _ => false,
+    };
+    let mut depth = 0;
+    let mut has_inner_semicolon = false;
+    for pair in tokens.windows(2) {
+        match pair[0].token_type {
+            TokenType::LeftBrace => depth += 1,
+            TokenType::RightBrace => depth -= 1,
+            TokenType::Semicolon if depth == 0 && pair[1].token_type != TokenType::Eof => {
+                has_inner_semicolon = true;
+            }
+            _ => {}
+        }
+    }
+    starts_with_statement || has_inner_semicolon
+}
+
+pub fn parse_program(tokens: &[Token]) -> Result<Program, Vec<ParseError>> {
+    let mut parser = Parser::new(tokens);
+    parser.program()
 }
 
-pub fn parse_program(tokens: &[Token]) -> Result<Program, ParseError> {
+/// Parse a line entered in the REPL. It's a program, except that the last
+/// expression statement may omit its `;`.
+pub fn parse_repl_line(tokens: &[Token]) -> Result<Program, Vec<ParseError>> {
     let mut parser = Parser::new(tokens);
+    parser.is_repl = true;
     parser.program()
 }
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::color;
+use crate::parser::{Expr, Function, Program, Slot, Stmt, Variable};
+use crate::symbol::Symbol;
+use crate::tokenizer::Pos;
+
+/// A static error, found before running the program
+pub struct ResolveError {
+    pub pos: Pos,
+    pub name: String,
+    pub msg: String,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pos = color::pos(format!("[{}]", self.pos));
+        let name = color::lexeme(format!("'{}'", self.name));
+        write!(f, "{} Error at {}: {}", pos, name, self.msg)
+    }
+}
+
+/// A local variable in a scope
+struct Local {
+    /// The position in the scope, which is the order of declaration
+    index: usize,
+    /// Whether its initializer was already resolved
+    is_defined: bool,
+}
+
+struct Resolver {
+    /// The local scopes, the most local is the last. They match the scopes
+    /// which the interpreter creates when running.
+    scopes: Vec<HashMap<Symbol, Local>>,
+    /// The globals declared by the program, and whether their initializers
+    /// were already resolved. The REPL resolves each line by itself, so it may
+    /// still redeclare them.
+    globals: HashMap<Symbol, bool>,
+    errors: Vec<ResolveError>,
+}
+
+impl Resolver {
+    fn error(&mut self, pos: Pos, name: Symbol, msg: &str) {
+        self.errors.push(ResolveError {
+            pos,
+            name: name.to_string(),
+            msg: msg.into(),
+        });
+    }
+
+    fn declare(&mut self, pos: Pos, name: Symbol) {
+        let is_new = match self.scopes.last_mut() {
+            Some(scope) if scope.contains_key(&name) => false,
+            Some(scope) => {
+                let local = Local {
+                    index: scope.len(),
+                    is_defined: false,
+                };
+                scope.insert(name, local);
+                true
+            }
+            None => self.globals.insert(name, false).is_none(),
+        };
+        if !is_new {
+            self.error(
+                pos,
+                name,
+                "Already a variable with this name in this scope.",
+            );
+        }
+    }
+
+    fn define(&mut self, name: Symbol) {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                if let Some(local) = scope.get_mut(&name) {
+                    local.is_defined = true;
+                }
+            }
+            None => {
+                self.globals.insert(name, true);
+            }
+        }
+    }
+
+    /// Find where a variable lives. If it's not in any local scope, it's global.
+    fn resolve(&self, variable: &Variable) {
+        let slot = self
+            .scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                let local = scope.get(&variable.name)?;
+                Some(Slot {
+                    depth,
+                    index: local.index,
+                })
+            });
+        variable.slot.set(slot);
+    }
+
+    fn read(&mut self, pos: Pos, variable: &Variable) {
+        let (is_declared_only, msg) = match self.scopes.last() {
+            Some(scope) => (
+                scope
+                    .get(&variable.name)
+                    .is_some_and(|local| !local.is_defined),
+                "Can't read local variable in its own initializer.",
+            ),
+            None => (
+                self.globals.get(&variable.name) == Some(&false),
+                "Can't read global variable in its own initializer.",
+            ),
+        };
+        if is_declared_only {
+            self.error(pos, variable.name, msg);
+        }
+        self.resolve(variable);
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(e) => self.expr(e),
+            Stmt::Print(exprs) => {
+                for e in exprs {
+                    self.expr(e);
+                }
+            }
+            Stmt::Function(function) => {
+                self.declare(function.pos, function.name);
+                self.define(function.name);
+                self.function(function);
+            }
+            Stmt::Class(class) => {
+                self.declare(class.pos, class.name);
+                self.define(class.name);
+                if let Some(superclass) = &class.superclass {
+                    self.expr(superclass);
+                    self.scopes.push(HashMap::new());
+                    let name = Symbol::super_();
+                    self.declare(class.pos, name);
+                    self.define(name);
+                }
+                // Methods see `this` in a scope of its own, as they do when running
+                self.scopes.push(HashMap::new());
+                let name = Symbol::this();
+                self.declare(class.pos, name);
+                self.define(name);
+                for method in &class.methods {
+                    self.function(method);
+                }
+                self.scopes.pop();
+                if class.superclass.is_some() {
+                    self.scopes.pop();
+                }
+            }
+            Stmt::IfStmt {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+            } => {
+                self.expr(subject);
+                for (value, stmts) in cases {
+                    self.expr(value);
+                    self.scopes.push(HashMap::new());
+                    self.stmts(stmts);
+                    self.scopes.pop();
+                }
+                if let Some(stmts) = default {
+                    self.scopes.push(HashMap::new());
+                    self.stmts(stmts);
+                    self.scopes.pop();
+                }
+            }
+            Stmt::Return(e) => {
+                if let Some(e) = e {
+                    self.expr(e);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                self.expr(condition);
+                self.stmt(body);
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                }
+            }
+            Stmt::DoWhile { body, condition } => {
+                self.stmt(body);
+                self.expr(condition);
+            }
+            Stmt::Repeat { count, body } => {
+                self.expr(count);
+                self.stmt(body);
+            }
+            Stmt::ForEach {
+                pos,
+                var,
+                iterable,
+                body,
+            } => {
+                self.expr(iterable);
+                self.scopes.push(HashMap::new());
+                self.declare(*pos, *var);
+                self.define(*var);
+                self.stmt(body);
+                self.scopes.pop();
+            }
+            Stmt::Break | Stmt::Continue | Stmt::Empty => {}
+            Stmt::Var {
+                pos,
+                name,
+                initializer,
+                ..
+            } => {
+                self.declare(*pos, *name);
+                if let Some(e) = initializer {
+                    self.expr(e);
+                }
+                self.define(*name);
+            }
+            Stmt::Block(stmts) => {
+                self.scopes.push(HashMap::new());
+                self.stmts(stmts);
+                self.scopes.pop();
+            }
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        // The parameters and the body share a single scope
+        self.scopes.push(HashMap::new());
+        for param in &function.params {
+            self.declare(function.pos, *param);
+            self.define(*param);
+        }
+        self.stmts(&function.body);
+        self.scopes.pop();
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(_, _) => {}
+            Expr::Variable(pos, variable) => self.read(*pos, variable),
+            Expr::Increment(pos, increment) => self.read(*pos, &increment.target),
+            Expr::Unary(_, unary) => self.expr(&unary.expr),
+            Expr::Binary(_, binary) => {
+                self.expr(&binary.left);
+                self.expr(&binary.right);
+            }
+            Expr::Logical(_, logical) => {
+                self.expr(&logical.left);
+                self.expr(&logical.right);
+            }
+            Expr::Grouping(_, grouping) => self.expr(&grouping.0),
+            Expr::Assign(_, assign) => {
+                self.expr(&assign.rhs);
+                self.resolve(&assign.target);
+            }
+            Expr::Call(_, call) => {
+                self.expr(&call.callee);
+                for arg in &call.args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Sequence(_, sequence) => {
+                for expr in &sequence.0 {
+                    self.expr(expr);
+                }
+            }
+            Expr::Array(_, array) => {
+                for item in &array.0 {
+                    self.expr(item);
+                }
+            }
+            Expr::Get(_, get) => self.expr(&get.object),
+            Expr::Set(_, set) => {
+                self.expr(&set.object);
+                self.expr(&set.rhs);
+            }
+            Expr::This(_, this) => self.resolve(this),
+            Expr::Super(_, sup) => self.resolve(&sup.superclass),
+            Expr::Map(_, map) => {
+                for (key, value) in &map.0 {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Expr::Index(_, index) => {
+                self.expr(&index.object);
+                self.expr(&index.index);
+            }
+            Expr::IndexAssign(_, index_assign) => {
+                self.expr(&index_assign.object);
+                self.expr(&index_assign.index);
+                self.expr(&index_assign.rhs);
+            }
+            Expr::Ternary(_, ternary) => {
+                self.expr(&ternary.condition);
+                self.expr(&ternary.then_branch);
+                self.expr(&ternary.else_branch);
+            }
+            Expr::Lambda(_, function) => self.function(function),
+        }
+    }
+}
+
+impl Resolver {
+    fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            globals: HashMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn finish(self) -> Result<(), Vec<ResolveError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Check the program for static errors, such as reading a local variable in
+/// its own initializer, or declaring two variables with the same name in
+/// the same local scope. Also find where each local variable lives, which the
+/// program needs before it can run.
+pub fn resolve(program: &Program) -> Result<(), Vec<ResolveError>> {
+    let mut resolver = Resolver::new();
+    resolver.stmts(&program.stmts);
+    resolver.finish()
+}
+
+/// Like `resolve()`, for a single expression
+pub fn resolve_expr(expr: &Expr) -> Result<(), Vec<ResolveError>> {
+    let mut resolver = Resolver::new();
+    resolver.expr(expr);
+    resolver.finish()
+}
@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ptr;
+use std::sync::{LazyLock, Mutex};
+
+/// An interned string. Since every string is interned once, comparing and
+/// hashing it only touches its address, and reading it needs no lock.
+#[derive(Clone, Copy)]
+pub struct Symbol(&'static str);
+
+/// The interned strings are leaked, so they live as long as the process. There
+/// are only as many as there are distinct names in the sources we've read.
+static INTERNER: LazyLock<Mutex<HashSet<&'static str>>> = LazyLock::new(Default::default);
+
+impl Symbol {
+    pub fn intern(s: &str) -> Symbol {
+        let mut interner = INTERNER.lock().unwrap();
+        if let Some(&s) = interner.get(s) {
+            return Symbol(s);
+        }
+        let s: &'static str = Box::leak(s.into());
+        interner.insert(s);
+        Symbol(s)
+    }
+
+    /// The original string
+    pub fn as_str(self) -> &'static str {
+        self.0
+    }
+
+    /// `this`, which methods are bound to
+    pub fn this() -> Symbol {
+        static THIS: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("this"));
+        *THIS
+    }
+
+    /// `super`, which subclass methods see
+    pub fn super_() -> Symbol {
+        static SUPER: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("super"));
+        *SUPER
+    }
+
+    /// `init`, the name of the initializer method
+    pub fn init() -> Symbol {
+        static INIT: LazyLock<Symbol> = LazyLock::new(|| Symbol::intern("init"));
+        *INIT
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        ptr::hash(self.0, state)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol({:?})", self.0)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
@@ -1,14 +1,29 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::color;
+use crate::json;
+use crate::symbol::Symbol;
+
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum TokenType {
     // Single-character tokens
+    Ampersand,
+    Caret,
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
+    Percent,
+    Pipe,
     Plus,
+    Question,
     Semicolon,
     Slash,
     Star,
@@ -20,8 +35,16 @@ pub enum TokenType {
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
+    MinusEqual,
+    MinusMinus,
+    PlusEqual,
+    PlusPlus,
+    SlashEqual,
+    StarEqual,
 
     // Literals
     Identifier,
@@ -30,17 +53,27 @@ pub enum TokenType {
 
     // Keywords
     And,
+    Break,
+    Case,
     Class,
+    Const,
+    Continue,
+    Default,
+    Do,
     Else,
     False,
     Fun,
     For,
+    Foreach,
     If,
+    In,
     Nil,
     Or,
     Print,
+    Repeat,
     Return,
     Super,
+    Switch,
     This,
     True,
     Var,
@@ -54,17 +87,27 @@ use TokenType::*;
 fn get_keyword(name: &str) -> Option<TokenType> {
     match name {
         "and" => Some(And),
+        "break" => Some(Break),
+        "case" => Some(Case),
         "class" => Some(Class),
+        "const" => Some(Const),
+        "continue" => Some(Continue),
+        "default" => Some(Default),
+        "do" => Some(Do),
         "else" => Some(Else),
         "false" => Some(False),
         "fun" => Some(Fun),
         "for" => Some(For),
+        "foreach" => Some(Foreach),
         "if" => Some(If),
+        "in" => Some(In),
         "nil" => Some(Nil),
         "or" => Some(Or),
         "print" => Some(Print),
+        "repeat" => Some(Repeat),
         "return" => Some(Return),
         "super" => Some(Super),
+        "switch" => Some(Switch),
         "this" => Some(This),
         "true" => Some(True),
         "var" => Some(Var),
@@ -78,12 +121,20 @@ impl TokenType {
         match self {
             LeftParen => "LEFT_PAREN",
             RightParen => "RIGHT_PAREN",
+            Ampersand => "AMPERSAND",
+            Caret => "CARET",
             LeftBrace => "LEFT_BRACE",
             RightBrace => "RIGHT_BRACE",
+            LeftBracket => "LEFT_BRACKET",
+            RightBracket => "RIGHT_BRACKET",
+            Colon => "COLON",
             Comma => "COMMA",
             Dot => "DOT",
             Minus => "MINUS",
+            Percent => "PERCENT",
+            Pipe => "PIPE",
             Plus => "PLUS",
+            Question => "QUESTION",
             Semicolon => "SEMICOLON",
             Slash => "SLASH",
             Star => "STAR",
@@ -94,25 +145,43 @@ impl TokenType {
             EqualEqual => "EQUAL_EQUAL",
             Greater => "GREATER",
             GreaterEqual => "GREATER_EQUAL",
+            GreaterGreater => "GREATER_GREATER",
             Less => "LESS",
             LessEqual => "LESS_EQUAL",
+            LessLess => "LESS_LESS",
+            MinusEqual => "MINUS_EQUAL",
+            MinusMinus => "MINUS_MINUS",
+            PlusEqual => "PLUS_EQUAL",
+            PlusPlus => "PLUS_PLUS",
+            SlashEqual => "SLASH_EQUAL",
+            StarEqual => "STAR_EQUAL",
 
             Identifier => "IDENTIFIER",
             StringLiteral => "STRING",
             Number => "NUMBER",
 
             And => "AND",
+            Break => "BREAK",
+            Case => "CASE",
             Class => "CLASS",
+            Const => "CONST",
+            Continue => "CONTINUE",
+            Default => "DEFAULT",
+            Do => "DO",
             Else => "ELSE",
             False => "FALSE",
             Fun => "FUN",
             For => "FOR",
+            Foreach => "FOREACH",
             If => "IF",
+            In => "IN",
             Nil => "NIL",
             Or => "OR",
             Print => "PRINT",
+            Repeat => "REPEAT",
             Return => "RETURN",
             Super => "SUPER",
+            Switch => "SWITCH",
             This => "THIS",
             True => "TRUE",
             Var => "VAR",
@@ -123,40 +192,258 @@ impl TokenType {
     }
 }
 
+/// A position in the source. Both the line and the column start from 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, col {}", self.line, self.column)
+    }
+}
+
+/// The source text of a token. Identifiers and keywords are interned, since
+/// the parser uses them as names. Other tokens, like literals, own their text.
+#[derive(Clone, Debug)]
+pub enum Lexeme {
+    Name(Symbol),
+    Text(String),
+}
+
+impl Lexeme {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Lexeme::Name(name) => name.as_str(),
+            Lexeme::Text(text) => text,
+        }
+    }
+}
+
+impl fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Lexeme,
     pub line: usize,
+    /// The column where the token starts
+    pub column: usize,
+    /// The byte offset where the token starts in the source
+    pub start: usize,
+    /// The byte offset just past the token's end
+    pub end: usize,
+}
+
+/// Format a number the way Lox prints it: integers without a decimal point,
+/// and other numbers with the fewest digits that read back the same. Very large
+/// and very small numbers use an exponent, like `1e20`.
+pub fn format_number(x: f64) -> String {
+    let s = format!("{:?}", x);
+    match s.strip_suffix(".0") {
+        Some(integer) => integer.into(),
+        None => s,
+    }
+}
+
+/// Format a number literal the way `tokenize` and `parse` show it. It's like
+/// `format_number()`, except that integers keep a `.0`, so they still look
+/// like numbers.
+pub fn format_number_literal(x: f64) -> String {
+    let s = format_number(x);
+    if x.fract() == 0.0 && !s.contains('e') {
+        s + ".0"
+    } else {
+        s
+    }
+}
+
+/// Quote a string as a Lox string literal, escaping chars where needed
+pub fn quote_string(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+/// Translate the char following a backslash in a string literal
+fn escaped_char(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '\\' => Some('\\'),
+        '"' => Some('"'),
+        _ => None,
+    }
+}
+
+/// Parse the rest of a `\u{XXXX}` escape, following the `u`, with 1 to 6 hex
+/// digits. Return the char and how many chars the escape took after the `u`.
+fn unicode_escape(mut chars: impl Iterator<Item = char>) -> Result<(char, usize), String> {
+    if chars.next() != Some('{') {
+        return Err("Expecting '{' after \\u".into());
+    }
+    let mut digits = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => digits.push(c),
+            _ => return Err("Expecting 1 to 6 hex digits and '}' in \\u{...}".into()),
+        }
+    }
+    if digits.is_empty() {
+        return Err("Expecting 1 to 6 hex digits and '}' in \\u{...}".into());
+    }
+    let code = u32::from_str_radix(&digits, 16).unwrap();
+    match char::from_u32(code) {
+        Some(c) => Ok((c, digits.len() + 2)),
+        None => Err(format!("Invalid unicode code point: \\u{{{}}}", digits)),
+    }
 }
 
 impl Token {
+    pub fn pos(&self) -> Pos {
+        Pos {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// The name of an identifier or keyword token
+    pub fn name(&self) -> Symbol {
+        match &self.lexeme {
+            Lexeme::Name(name) => *name,
+            Lexeme::Text(text) => panic!("{:?} isn't a name", text),
+        }
+    }
+
+    /// The value of a string literal, with its escape sequences interpreted.
+    /// The scanner already made sure that they are all valid.
+    pub fn string_value(&self) -> String {
+        let mut value = String::new();
+        let lexeme = self.lexeme.as_str();
+        let mut chars = lexeme[1..lexeme.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' && chars.as_str().starts_with('u') {
+                chars.next();
+                let (escaped, len) = unicode_escape(chars.clone()).expect("invalid unicode escape");
+                value.push(escaped);
+                chars.nth(len - 1);
+            } else if c == '\\' {
+                let escaped = chars.next().and_then(escaped_char);
+                value.push(escaped.expect("invalid escape sequence"));
+            } else {
+                value.push(c);
+            }
+        }
+        value
+    }
+
+    /// The value of a number literal. The scanner already made sure it's valid.
+    pub fn number_value(&self) -> f64 {
+        let s = self.lexeme.as_str().replace('_', "");
+        let radix = match s.get(..2) {
+            Some("0x" | "0X") => 16,
+            Some("0b" | "0B") => 2,
+            _ => return s.parse::<f64>().unwrap(),
+        };
+        // Accumulate in f64, so big literals lose precision instead of overflowing
+        s[2..].chars().fold(0.0, |acc, c| {
+            acc * radix as f64 + c.to_digit(radix).unwrap() as f64
+        })
+    }
+
     pub fn literal_str(&self) -> String {
         match self.token_type {
-            StringLiteral => self.lexeme[1..self.lexeme.len() - 1].to_string(),
+            StringLiteral => self.string_value(),
+            Number => format_number_literal(self.number_value()),
+            _ => "null".into(),
+        }
+    }
+
+    /// A JSON object with the token's type, lexeme, literal value, position
+    /// and byte range
+    pub fn to_json(&self) -> String {
+        let literal = match self.token_type {
+            StringLiteral => json::quote(&self.string_value()),
             Number => {
-                let x = self.lexeme.parse::<f64>().unwrap();
-                format!("{:?}", x)
+                let x = self.number_value();
+                if x.is_finite() {
+                    format!("{:?}", x)
+                } else {
+                    "null".into()
+                }
             }
             _ => "null".into(),
-        }
+        };
+        format!(
+            r#"{{"type": {}, "lexeme": {}, "literal": {}, "line": {}, "column": {}, "start": {}, "end": {}}}"#,
+            json::quote(self.token_type.name()),
+            json::quote(self.lexeme.as_str()),
+            literal,
+            self.line,
+            self.column,
+            self.start,
+            self.end
+        )
+    }
+}
+
+/// An error found by the tokenizer
+pub struct ScanError {
+    pub pos: Pos,
+    pub msg: String,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pos = color::pos(format!("[{}]", self.pos));
+        write!(f, "{} Error: {}", pos, self.msg)
     }
 }
 
 struct Scanner {
     source: Vec<char>,
     current: usize,
+    /// The byte offset of the next char, which differs from `current` after
+    /// non-ASCII chars
+    offset: usize,
     line: usize,
-    had_error: bool,
+    /// The column of the next char
+    column: usize,
+    /// A tab advances the column to the next multiple of this, plus 1
+    tab_width: usize,
+    /// Errors found, which weren't yet taken
+    errors: VecDeque<ScanError>,
 }
 
 impl Scanner {
-    fn new(source: &str) -> Self {
+    fn new(source: &str, tab_width: usize) -> Self {
         Scanner {
             source: source.chars().collect(),
             current: 0,
+            offset: 0,
             line: 1,
-            had_error: false,
+            column: 1,
+            tab_width: tab_width.max(1),
+            errors: VecDeque::new(),
         }
     }
 
@@ -169,8 +456,14 @@ impl Scanner {
         assert!(self.has_more());
         let c = self.source[self.current];
         self.current += 1;
+        self.offset += c.len_utf8();
         if c == '\n' {
             self.line += 1;
+            self.column = 1;
+        } else if c == '\t' {
+            self.column = ((self.column - 1) / self.tab_width + 1) * self.tab_width + 1;
+        } else {
+            self.column += 1;
         }
         c
     }
@@ -199,9 +492,23 @@ impl Scanner {
         is_match
     }
 
+    fn pos(&self) -> Pos {
+        Pos {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Report an error at the current position
     fn error(&mut self, msg: &str) {
-        eprintln!("[line {}] Error: {}", self.line, msg);
-        self.had_error = true;
+        self.error_at(self.pos(), msg);
+    }
+
+    fn error_at(&mut self, pos: Pos, msg: &str) {
+        self.errors.push_back(ScanError {
+            pos,
+            msg: msg.into(),
+        });
     }
 
     fn substr(&self, start: usize, end: usize) -> String {
@@ -209,9 +516,26 @@ impl Scanner {
     }
 }
 
+/// Consume digits following a digit. A single `_` is allowed between two digits,
+/// so `1_000` is one number, while `1__0` is the number `1` followed by `__0`.
+fn scan_digits(scanner: &mut Scanner, radix: u32) {
+    loop {
+        let is_digit = |c: Option<char>| c.is_some_and(|c| c.is_digit(radix));
+        if is_digit(scanner.peek())
+            || (scanner.peek() == Some('_') && is_digit(scanner.peek_next()))
+        {
+            scanner.advance();
+        } else {
+            break;
+        }
+    }
+}
+
 /// Consume at least one char. Return a Token if consumed a token.
 fn scan_token(scanner: &mut Scanner) -> Option<Token> {
     let start = scanner.current;
+    let start_offset = scanner.offset;
+    let start_pos = scanner.pos();
     let c = scanner.advance();
     let token_type = match c {
         ' ' | '\t' | '\n' => return None,
@@ -219,12 +543,39 @@ fn scan_token(scanner: &mut Scanner) -> Option<Token> {
         ')' => RightParen,
         '{' => LeftBrace,
         '}' => RightBrace,
+        '[' => LeftBracket,
+        ']' => RightBracket,
+        ':' => Colon,
         ',' => Comma,
         '.' => Dot,
-        '-' => Minus,
-        '+' => Plus,
+        '-' => {
+            if scanner.is_match('=') {
+                MinusEqual
+            } else if scanner.is_match('-') {
+                MinusMinus
+            } else {
+                Minus
+            }
+        }
+        '%' => Percent,
+        '+' => {
+            if scanner.is_match('=') {
+                PlusEqual
+            } else if scanner.is_match('+') {
+                PlusPlus
+            } else {
+                Plus
+            }
+        }
+        '?' => Question,
         ';' => Semicolon,
-        '*' => Star,
+        '*' => {
+            if scanner.is_match('=') {
+                StarEqual
+            } else {
+                Star
+            }
+        }
 
         '!' | '=' | '>' | '<' => {
             if scanner.is_match('=') {
@@ -235,6 +586,10 @@ fn scan_token(scanner: &mut Scanner) -> Option<Token> {
                     '<' => LessEqual,
                     _ => unreachable!(),
                 }
+            } else if c == '>' && scanner.is_match('>') {
+                GreaterGreater
+            } else if c == '<' && scanner.is_match('<') {
+                LessLess
             } else {
                 match c {
                     '!' => Bang,
@@ -245,6 +600,9 @@ fn scan_token(scanner: &mut Scanner) -> Option<Token> {
                 }
             }
         }
+        '&' => Ampersand,
+        '|' => Pipe,
+        '^' => Caret,
 
         '/' => {
             if scanner.is_match('/') {
@@ -255,36 +613,100 @@ fn scan_token(scanner: &mut Scanner) -> Option<Token> {
                     }
                 }
                 return None;
+            } else if scanner.is_match('*') {
+                // Block comments may be nested, so we count how deep we are
+                let mut depth = 1;
+                while depth > 0 {
+                    if !scanner.has_more() {
+                        scanner.error("Unterminated block comment.");
+                        return None;
+                    }
+                    let c = scanner.advance();
+                    if c == '/' && scanner.is_match('*') {
+                        depth += 1;
+                    } else if c == '*' && scanner.is_match('/') {
+                        depth -= 1;
+                    }
+                }
+                return None;
+            } else if scanner.is_match('=') {
+                SlashEqual
             } else {
                 Slash
             }
         }
 
         '"' => {
+            let mut is_valid = true;
             loop {
                 if !scanner.has_more() {
-                    scanner.error("Unterminated string.");
+                    // Point to where the string opened, not to the end of the file
+                    scanner.error_at(start_pos, "Unterminated string.");
                     return None;
                 }
                 let c = scanner.advance();
                 if c == '"' {
                     break;
                 }
+                if c == '\\' && scanner.has_more() {
+                    let c = scanner.advance();
+                    if c == 'u' {
+                        let rest = scanner.source[scanner.current..].iter().copied();
+                        match unicode_escape(rest) {
+                            Ok((_, len)) => {
+                                for _ in 0..len {
+                                    scanner.advance();
+                                }
+                            }
+                            Err(msg) => {
+                                scanner.error(&msg);
+                                is_valid = false;
+                            }
+                        }
+                    } else if escaped_char(c).is_none() {
+                        scanner.error(&format!("Invalid escape sequence: \\{}", c));
+                        is_valid = false;
+                    }
+                }
+            }
+            if !is_valid {
+                return None;
             }
             StringLiteral
         }
 
         '0'..='9' => {
-            while scanner.peek().is_some_and(|c| c.is_ascii_digit()) {
-                scanner.advance();
-            }
-            if scanner.peek() == Some('.')
-                && scanner.peek_next().is_some_and(|c| c.is_ascii_digit())
-            {
-                scanner.advance();
-            }
-            while scanner.peek().is_some_and(|c| c.is_ascii_digit()) {
+            let radix = match (c, scanner.peek()) {
+                ('0', Some('x' | 'X')) => Some((16, "hexadecimal")),
+                ('0', Some('b' | 'B')) => Some((2, "binary")),
+                _ => None,
+            };
+            if let Some((radix, radix_name)) = radix {
                 scanner.advance();
+                if !scanner.peek().is_some_and(|c| c.is_digit(radix)) {
+                    scanner.error(&format!("Expecting {} digits.", radix_name));
+                    return None;
+                }
+                scan_digits(scanner, radix);
+            } else {
+                scan_digits(scanner, 10);
+                if scanner.peek() == Some('.')
+                    && scanner.peek_next().is_some_and(|c| c.is_ascii_digit())
+                {
+                    scanner.advance();
+                    scan_digits(scanner, 10);
+                }
+                if let Some('e' | 'E') = scanner.peek() {
+                    scanner.advance();
+                    if let Some('+' | '-') = scanner.peek() {
+                        scanner.advance();
+                    }
+                    if !scanner.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        scanner.error("Expecting exponent digits.");
+                        return None;
+                    }
+                    scan_digits(scanner, 10);
+                }
             }
             Number
         }
@@ -304,30 +726,88 @@ fn scan_token(scanner: &mut Scanner) -> Option<Token> {
         }
 
         _ => {
-            scanner.error(&format!("Unexpected character: {}", c));
+            scanner.error_at(start_pos, &format!("Unexpected character: {}", c));
             return None;
         }
     };
-    let lexeme = scanner.substr(start, scanner.current);
+    let text = scanner.substr(start, scanner.current);
+    let lexeme = if text.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) {
+        Lexeme::Name(Symbol::intern(&text))
+    } else {
+        Lexeme::Text(text)
+    };
     Some(Token {
         token_type,
         lexeme,
-        line: scanner.line,
+        line: start_pos.line,
+        column: start_pos.column,
+        start: start_offset,
+        end: scanner.offset,
     })
 }
 
-pub fn tokenize(contents: &str) -> (Vec<Token>, bool) {
+/// The default tab width for counting columns, as in most terminals
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+pub fn tokenize(contents: &str) -> (Vec<Token>, Vec<ScanError>) {
+    tokenize_with_tab_width(contents, DEFAULT_TAB_WIDTH)
+}
+
+/// Like `tokenize()`, with tab stops every `tab_width` columns
+pub fn tokenize_with_tab_width(contents: &str, tab_width: usize) -> (Vec<Token>, Vec<ScanError>) {
     let mut tokens = Vec::<Token>::new();
-    let mut scanner = Scanner::new(contents);
-    while scanner.has_more() {
-        if let Some(token) = scan_token(&mut scanner) {
-            tokens.push(token);
+    let mut errors = Vec::<ScanError>::new();
+    for item in TokenStream::new(contents, tab_width) {
+        match item {
+            Ok(token) => tokens.push(token),
+            Err(err) => errors.push(err),
+        }
+    }
+    (tokens, errors)
+}
+
+/// The tokens of a source, scanned one at a time. Errors come in the place of
+/// the tokens they spoil, and the last item is always a single `Eof` token.
+pub struct TokenStream {
+    scanner: Scanner,
+    is_done: bool,
+}
+
+impl TokenStream {
+    pub fn new(contents: &str, tab_width: usize) -> Self {
+        TokenStream {
+            scanner: Scanner::new(contents, tab_width),
+            is_done: false,
+        }
+    }
+}
+
+impl Iterator for TokenStream {
+    type Item = Result<Token, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(err) = self.scanner.errors.pop_front() {
+                return Some(Err(err));
+            }
+            if !self.scanner.has_more() {
+                break;
+            }
+            if let Some(token) = scan_token(&mut self.scanner) {
+                return Some(Ok(token));
+            }
+        }
+        if self.is_done {
+            return None;
         }
+        self.is_done = true;
+        Some(Ok(Token {
+            token_type: Eof,
+            lexeme: Lexeme::Text(String::new()),
+            line: self.scanner.line,
+            column: self.scanner.column,
+            start: self.scanner.offset,
+            end: self.scanner.offset,
+        }))
     }
-    tokens.push(Token {
-        token_type: Eof,
-        lexeme: "".into(),
-        line: scanner.line,
-    });
-    (tokens, scanner.had_error)
 }
@@ -0,0 +1,76 @@
+mod common;
+
+use common::{run, run_binary};
+
+#[test]
+fn multi_word_kinds_are_kebab_case() {
+    let src = "var a = [1]; a[0] = 2; do { a[0] += 1; } while (false);";
+    let out = run_binary(&["parse-program", "-", "--json"], src);
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert!(
+        out.stdout.contains("\"kind\": \"index-assign\""),
+        "{}",
+        out.stdout
+    );
+    assert!(
+        out.stdout.contains("\"kind\": \"do-while\""),
+        "{}",
+        out.stdout
+    );
+    assert!(!out.stdout.contains('_'), "{}", out.stdout);
+
+    let out = run_binary(&["parse-program", "-", "--ast-dot"], src);
+    assert!(out.stdout.contains("do-while"), "{}", out.stdout);
+
+    let out = run_binary(&["run", "-", "--profile"], src);
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert!(out.stderr.contains("stmt do-while"), "{}", out.stderr);
+    assert!(out.stderr.contains("expr index-assign"), "{}", out.stderr);
+}
+
+#[test]
+fn nested_block_display() {
+    let out = run_binary(
+        &["parse-program", "-"],
+        "{ var a = 1; { print a; } }\nprint 2;",
+    );
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(
+        out.stdout,
+        "(\n  (block\n    (var a 1.0)\n    (block\n      (print (variable a))\n    )\n  )\n  (print 2.0)\n)\n"
+    );
+}
+
+#[test]
+fn print_with_several_expressions() {
+    assert_eq!(
+        run("print 1, \"a\", nil; print 2; var x = [1]; print x, x;"),
+        "1 a nil\n2\n[1] [1]\n"
+    );
+    let out = run_binary(&["parse-program", "-"], "print 1, 2;");
+    assert_eq!(out.stdout, "(\n  (print 1.0 2.0)\n)\n");
+}
+
+#[test]
+fn profile_counts_evaluations_by_kind() {
+    let out = run_binary(
+        &["run", "--profile", "-"],
+        "var i = 0; while (i < 3) i = i + 1; print i;",
+    );
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "3\n");
+    assert_eq!(
+        out.stderr,
+        "       count  node\n\
+         \x20          8  expr literal\n\
+         \x20          8  expr variable\n\
+         \x20          7  expr binary\n\
+         \x20          3  expr assign\n\
+         \x20          3  stmt expr\n\
+         \x20          1  stmt print\n\
+         \x20          1  stmt var\n\
+         \x20          1  stmt while\n"
+    );
+    let out = run_binary(&["run", "-"], "print 1;");
+    assert_eq!(out.stderr, "");
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use common::{run_binary, run_program};
+
+const RECURSE: &str = "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); } print f(100);";
+
+#[test]
+fn stack_size_accepts_both_forms() {
+    let forms: [&[&str]; 2] = [&["--stack-size=64M"], &["--stack-size", "64m"]];
+    for flags in forms {
+        let out = run_program(RECURSE, flags);
+        assert_eq!(
+            (out.code, out.stdout.as_str()),
+            (0, "100\n"),
+            "{}",
+            out.stderr
+        );
+    }
+}
+
+#[test]
+fn value_flags_accept_a_separate_value() {
+    let out = run_program(RECURSE, &["--max-depth", "50"]);
+    assert_eq!(out.code, 70);
+    assert!(out.stderr.contains("Stack overflow."), "{}", out.stderr);
+}
+
+#[test]
+fn bad_stack_size_is_a_usage_error() {
+    let out = run_program(RECURSE, &["--stack-size"]);
+    assert_eq!(out.code, 64);
+    assert!(
+        out.stderr.contains("Missing value for --stack-size"),
+        "{}",
+        out.stderr
+    );
+    let out = run_program(RECURSE, &["--stack-size=12x"]);
+    assert_eq!(out.code, 64);
+    assert!(
+        out.stderr.contains("Invalid --stack-size: 12x"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn unknown_options_are_a_usage_error() {
+    for flag in ["--bogus", "--echo=1", "--json-errors=yes", "--max-depth5"] {
+        let out = run_program("print 1;", &[flag]);
+        assert_eq!(out.code, 64, "{}", flag);
+        assert_eq!(out.stdout, "");
+        assert!(
+            out.stderr
+                .starts_with(&format!("Unknown option: {}\n", flag)),
+            "{}",
+            out.stderr
+        );
+    }
+    let out = run_binary(&["frobnicate", "-"], "");
+    assert_eq!(out.code, 64);
+    assert!(out.stderr.starts_with("Unknown command: frobnicate\n"));
+}
+
+#[test]
+fn help_lists_every_option() {
+    let out = run_binary(&["--help"], "");
+    assert_eq!(out.code, 0);
+    for flag in [
+        "--json ",
+        "--json-errors",
+        "--offsets",
+        "--max-depth=N",
+        "--truthy=MODE",
+    ] {
+        assert!(
+            out.stdout.contains(flag),
+            "{} is missing from:\n{}",
+            flag,
+            out.stdout
+        );
+    }
+    let out = run_binary(&["tokenize", "-", "--json"], "x");
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert!(
+        out.stdout.starts_with("[\n  {\"type\": \"IDENTIFIER\""),
+        "{}",
+        out.stdout
+    );
+}
+
+#[test]
+fn unallocatable_stack_is_reported() {
+    let out = run_program(RECURSE, &["--max-depth=1000000000000"]);
+    assert_eq!(out.code, 71);
+    assert!(
+        out.stderr.contains("Can't start the interpreter"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn commands_which_dont_run_code_ignore_max_depth_for_the_stack() {
+    let out = run_binary(&["tokenize", "-", "--max-depth=1000000000000"], "print 1;");
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert!(out.stdout.ends_with("EOF  null\n"), "{}", out.stdout);
+}
+
+#[test]
+fn repl_continues_incomplete_statements_and_survives_errors() {
+    let input = "fun add(a, b) {\n  return a + b;\n}\nprint add(1, 2);\nprint (;\nprint \"still\";\n1 + 2;\n";
+    let out = run_binary(&["repl"], input);
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "3\nstill\n3\n");
+    assert_eq!(
+        out.stderr,
+        "[line 1, col 8] Error at ';': Unexpected token\n"
+    );
+}
+
+#[test]
+fn echo_prints_top_level_expression_values() {
+    let src = "var x = 1;\nx + 1;\nprint \"p\";\n\"s\";\nfun f() {}\nf();\n{ x; }\n";
+    let out = run_program(src, &["--echo"]);
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "2\np\ns\nnil\n");
+    let out = run_program(src, &[]);
+    assert_eq!(out.stdout, "p\n");
+}
+
+#[test]
+fn quiet_parse_prints_only_errors() {
+    let out = run_binary(&["parse", "--quiet", "-"], "1 + 2");
+    assert_eq!((out.code, out.stdout.as_str()), (0, ""));
+    let out = run_binary(&["parse-program", "--quiet", "-"], "print 1;");
+    assert_eq!((out.code, out.stdout.as_str()), (0, ""));
+    let out = run_binary(&["parse", "--quiet", "-"], "1 +");
+    assert_eq!(out.code, 65);
+    assert_eq!(out.stdout, "");
+    assert_eq!(
+        out.stderr,
+        "[line 1, col 4] Error at end: Not expecting end of file\n"
+    );
+}
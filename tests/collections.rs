@@ -0,0 +1,128 @@
+mod common;
+
+use common::{run, run_binary, run_err};
+
+#[test]
+fn nested_arrays_and_maps_compare_by_value() {
+    assert_eq!(run("print [1, [2, [3]]] == [1, [2, [3]]];"), "true\n");
+    assert_eq!(run("print [1, [2, [3]]] == [1, [2, [4]]];"), "false\n");
+    assert_eq!(run("print [1, 2] == [1, 2, 3];"), "false\n");
+    assert_eq!(
+        run("print {\"a\": [1, {\"b\": 2}]} == {\"a\": [1, {\"b\": 2}]};"),
+        "true\n"
+    );
+    assert_eq!(run("print {\"a\": 1} == {\"b\": 1};"), "false\n");
+    assert_eq!(run("print [1] == {\"0\": 1};"), "false\n");
+}
+
+#[test]
+fn alias_and_copy() {
+    let src = "
+        var a = [1];
+        var alias = a;
+        var copy = [1];
+        print a == alias;
+        print a == copy;
+        push(a, 2);
+        print alias;
+        print a == alias;
+        print a == copy;
+    ";
+    assert_eq!(run(src), "true\ntrue\n[1, 2]\ntrue\nfalse\n");
+}
+
+#[test]
+fn cyclic_equality_terminates() {
+    let src = "
+        var a = []; push(a, a);
+        var b = []; push(b, b);
+        print a == b;
+        var p = [1]; var q = [2]; push(p, q); push(q, p);
+        print p == q;
+        var m = {}; m[\"self\"] = m;
+        var n = {}; n[\"self\"] = n;
+        print m == n;
+    ";
+    assert_eq!(run(src), "true\nfalse\ntrue\n");
+}
+
+#[test]
+fn print_nested_values() {
+    assert_eq!(
+        run("print [1, \"a\", [nil, true], {\"k\": \"v\"}];"),
+        "[1, \"a\", [nil, true], {\"k\": \"v\"}]\n"
+    );
+}
+
+#[test]
+fn print_cyclic_values() {
+    assert_eq!(run("var a = [0]; a[0] = a; print a;"), "[[...]]\n");
+    assert_eq!(
+        run("var a = [1]; push(a, a); print str(a);"),
+        "[1, [...]]\n"
+    );
+    assert_eq!(
+        run("var m = {}; m[\"self\"] = m; m[\"list\"] = [m]; print m;"),
+        "{\"list\": [{...}], \"self\": {...}}\n"
+    );
+}
+
+#[test]
+fn shared_but_acyclic_values_print_fully() {
+    assert_eq!(run("var a = [1]; print [a, a];"), "[[1], [1]]\n");
+}
+
+#[test]
+fn map_literals_and_indexing() {
+    let src = "
+        var m = {\"a\": 1, 2: \"two\"};
+        print m[\"a\"]; print m[2]; print m[\"missing\"];
+        m[\"b\"] = {\"c\": [1]};
+        print m; print m[\"b\"][\"c\"];
+    ";
+    assert_eq!(
+        run(src),
+        "1\ntwo\nnil\n{2: \"two\", \"a\": 1, \"b\": {\"c\": [1]}}\n[1]\n"
+    );
+    assert_eq!(
+        run_err("var m = {};\nm[true] = 1;"),
+        "Map keys must be strings or numbers (but not NaN).\n[line 2, col 2]"
+    );
+}
+
+#[test]
+fn braces_start_a_block_at_statement_level() {
+    assert_eq!(
+        run("{ print 1; } {} var e = {}; print e; print {};"),
+        "1\n{}\n{}\n"
+    );
+}
+
+#[test]
+fn trailing_commas() {
+    assert_eq!(
+        run("fun f(a, b) { return a + b; } print f(1, 2,); print [1, 2,]; print []; print {\"a\": 1,}; print f(1, 2);"),
+        "3\n[1, 2]\n[]\n{\"a\": 1}\n3\n"
+    );
+    let out = run_binary(&["parse", "-"], "f(a,)");
+    assert_eq!(out.stdout, "(call (variable f) (variable a))\n");
+    assert!(run_err("print [,];").contains("Error at ',': Unexpected token"));
+    assert!(run_err("print [1,,2];").contains("Error at ',': Unexpected token"));
+    assert!(run_err("fun f() {} f(,);").contains("Error at ',': Unexpected token"));
+}
+
+#[test]
+fn bad_array_indexes() {
+    assert_eq!(
+        run_err("print [1][0.5];"),
+        "Array index must be an integer, got 0.5.\n[line 1, col 10]"
+    );
+    assert_eq!(
+        run_err("print [1][-1];"),
+        "Array index -1 out of range for length 1.\n[line 1, col 10]"
+    );
+    assert_eq!(
+        run_err("var a = [1];\na[1e21] = 2;"),
+        "Array index 1e21 out of range for length 1.\n[line 2, col 2]"
+    );
+}
@@ -0,0 +1,78 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use codecrafters_interpreter::interpreter::{interpret_program, Interpreter};
+use codecrafters_interpreter::parser::parse_program;
+use codecrafters_interpreter::resolver::resolve;
+use codecrafters_interpreter::tokenizer::tokenize;
+use codecrafters_interpreter::{run_source, RunError};
+
+/// Run a program which should succeed, and return what it printed
+pub fn run(src: &str) -> String {
+    run_source(src).unwrap_or_else(|err| panic!("program failed:\n{}", err))
+}
+
+/// Run a program which should fail, and return the error message
+pub fn run_err(src: &str) -> String {
+    match run_source(src) {
+        Ok(out) => panic!("program should have failed, but printed:\n{}", out),
+        Err(err) => err.to_string(),
+    }
+}
+
+/// Run a program which should succeed, with the interpreter's settings
+/// changed by `configure`, and return what it printed
+pub fn run_with(src: &str, configure: impl FnOnce(&mut Interpreter)) -> String {
+    try_run_with(src, configure).unwrap_or_else(|err| panic!("program failed:\n{}", err))
+}
+
+fn try_run_with(src: &str, configure: impl FnOnce(&mut Interpreter)) -> Result<String, RunError> {
+    let (tokens, scan_errors) = tokenize(src);
+    if !scan_errors.is_empty() {
+        return Err(RunError::Scan(scan_errors));
+    }
+    let program = parse_program(&tokens).map_err(RunError::Parse)?;
+    resolve(&program).map_err(RunError::Resolve)?;
+    let mut out = Vec::<u8>::new();
+    let mut ctx = Interpreter::new(&mut out);
+    configure(&mut ctx);
+    interpret_program(&program, &mut ctx).map_err(RunError::Runtime)?;
+    drop(ctx);
+    Ok(String::from_utf8(out).unwrap())
+}
+
+/// What running the binary gave
+pub struct Output {
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Run the binary with the given arguments, feeding it `stdin`
+pub fn run_binary(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-interpreter"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run the interpreter");
+    // The interpreter may exit without reading all of its input
+    let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+    let output = child.wait_with_output().unwrap();
+    Output {
+        code: output.status.code().expect("the interpreter was killed"),
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: String::from_utf8(output.stderr).unwrap(),
+    }
+}
+
+/// Run a program given on the standard input, with `run -` and extra flags
+pub fn run_program(src: &str, flags: &[&str]) -> Output {
+    let mut args = vec!["run", "-"];
+    args.extend_from_slice(flags);
+    run_binary(&args, src)
+}
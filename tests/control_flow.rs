@@ -0,0 +1,241 @@
+mod common;
+
+use common::{run, run_binary, run_err};
+
+#[test]
+fn return_exits_nested_if_while_and_function() {
+    let src = "
+        fun find(limit) {
+            var i = 0;
+            while (true) {
+                if (i * i > limit) {
+                    return i;
+                }
+                i = i + 1;
+            }
+            print \"unreachable\";
+        }
+        print find(50);
+    ";
+    assert_eq!(run(src), "8\n");
+}
+
+#[test]
+fn return_without_value_gives_nil() {
+    assert_eq!(run("fun f() { return; } print f();"), "nil\n");
+    assert_eq!(run("fun f() {} print f();"), "nil\n");
+}
+
+#[test]
+fn return_at_top_level_is_an_error() {
+    assert!(run_err("return 1;").contains("Can't return from top-level code."));
+}
+
+#[test]
+fn break_and_continue() {
+    let src = "
+        for (var i = 0; i < 10; i = i + 1) {
+            if (i == 5) break;
+            if (i % 2 == 0) continue;
+            print i;
+        }
+    ";
+    assert_eq!(run(src), "1\n3\n");
+}
+
+#[test]
+fn continue_in_for_runs_the_increment() {
+    let src = "
+        var n = 0;
+        for (var i = 0; i < 3; i = i + 1) { n = n + 1; continue; }
+        print n;
+    ";
+    assert_eq!(run(src), "3\n");
+}
+
+#[test]
+fn break_leaves_only_the_inner_loop() {
+    let src = "
+        for (var i = 0; i < 2; i = i + 1) {
+            while (true) { break; }
+            print i;
+        }
+    ";
+    assert_eq!(run(src), "0\n1\n");
+}
+
+#[test]
+fn break_and_continue_outside_loops_are_parse_errors() {
+    assert!(run_err("break;").contains("Can't use 'break' outside of a loop."));
+    assert!(run_err("while (true) { fun f() { continue; } }")
+        .contains("Can't use 'continue' outside of a loop."));
+}
+
+#[test]
+fn ternary_nests_to_the_right() {
+    let src = "
+        fun sign(x) { return x > 0 ? \"+\" : x < 0 ? \"-\" : \"0\"; }
+        print sign(3), sign(-3), sign(0);
+    ";
+    assert_eq!(run(src), "+ - 0\n");
+}
+
+#[test]
+fn ternary_evaluates_only_the_chosen_branch() {
+    let src = "
+        var log = \"\";
+        fun mark(s) { log = log + s; return s; }
+        print true ? mark(\"a\") : mark(\"b\");
+        print false ? mark(\"c\") : mark(\"d\");
+        print log;
+    ";
+    assert_eq!(run(src), "a\nd\nad\n");
+}
+
+#[test]
+fn ternary_display() {
+    let out = run_binary(&["parse", "-"], "a ? b : c ? d : e");
+    assert_eq!(
+        out.stdout,
+        "(? (variable a) (variable b) (? (variable c) (variable d) (variable e)))\n"
+    );
+}
+
+#[test]
+fn do_while_runs_at_least_once() {
+    assert_eq!(run("do print \"once\"; while (false);"), "once\n");
+    let src = "var i = 0; do { i = i + 1; } while (i < 3); print i;";
+    assert_eq!(run(src), "3\n");
+}
+
+#[test]
+fn do_while_with_break_and_continue() {
+    let src = "
+        var i = 0;
+        do {
+            i = i + 1;
+            if (i == 2) continue;
+            if (i == 4) break;
+            print i;
+        } while (i < 10);
+    ";
+    assert_eq!(run(src), "1\n3\n");
+}
+
+#[test]
+fn do_while_display() {
+    let out = run_binary(&["parse-program", "-"], "do print 1; while (false);");
+    assert_eq!(out.stdout, "(\n  (do (print 1.0) false)\n)\n");
+}
+
+#[test]
+fn comma_sequences_expressions() {
+    assert_eq!(run("print (1, 2, 3);"), "3\n");
+    let src = "
+        var log = \"\";
+        fun f(s) { log = log + s; return s; }
+        var last = (f(\"a\"), f(\"b\"));
+        print last, log;
+    ";
+    assert_eq!(run(src), "b ab\n");
+}
+
+#[test]
+fn comma_in_arguments_separates_them() {
+    assert_eq!(
+        run("fun f(a, b) { return b; } print f(1, 2); print f((1, 2), 3);"),
+        "2\n3\n"
+    );
+    assert_eq!(run("print [(1, 2), 3];"), "[2, 3]\n");
+}
+
+#[test]
+fn comma_in_for_increment() {
+    // The initializer is a single expression, so this assigns an undeclared `j`
+    let src = "for (var i = 0, j = 10; i < 3; i = i + 1) print i;";
+    assert!(run_err(src).contains("Variable 'j' not declared before assignment"));
+    let src = "var j = 10; for (var i = 0; i < 3; i = i + 1, j = j - 1) print j;";
+    assert_eq!(run(src), "10\n9\n8\n");
+}
+
+#[test]
+fn repeat_runs_the_body_n_times() {
+    assert_eq!(run("repeat (3) print \"hi\";"), "hi\nhi\nhi\n");
+    assert_eq!(run("repeat (0) print \"hi\";"), "");
+}
+
+#[test]
+fn repeat_evaluates_the_count_once() {
+    let src = "var n = 2; repeat (n) { n = n + 1; print n; }";
+    assert_eq!(run(src), "3\n4\n");
+}
+
+#[test]
+fn repeat_with_break_and_continue() {
+    let src =
+        "var i = 0; repeat (10) { i = i + 1; if (i == 2) continue; if (i == 4) break; print i; }";
+    assert_eq!(run(src), "1\n3\n");
+}
+
+#[test]
+fn repeat_count_must_be_a_natural_number() {
+    assert!(run_err("repeat (-1) print 1;").contains("Repeat count must not be negative, got -1."));
+    assert!(run_err("repeat (1.5) print 1;")
+        .contains("Repeat count must be an integer, got number 1.5."));
+    assert!(run_err("repeat (\"3\") print 1;")
+        .contains("Repeat count must be a number, got string \"3\"."));
+}
+
+#[test]
+fn foreach_sums_an_array() {
+    let src = "var sum = 0; foreach (x in [1, 2, 3, 4]) sum = sum + x; print sum;";
+    assert_eq!(run(src), "10\n");
+}
+
+#[test]
+fn foreach_with_break_and_continue() {
+    let src =
+        "foreach (x in [1, 2, 3, 4, 5]) { if (x == 2) continue; if (x == 4) break; print x; }";
+    assert_eq!(run(src), "1\n3\n");
+}
+
+#[test]
+fn foreach_binds_a_fresh_variable_per_item() {
+    let src = "
+        var fs = [];
+        foreach (x in [1, 2, 3]) push(fs, fun () { return x; });
+        foreach (f in fs) print f();
+    ";
+    assert_eq!(run(src), "1\n2\n3\n");
+}
+
+#[test]
+fn foreach_over_a_non_array_is_an_error() {
+    assert!(run_err("foreach (x in 3) print x;").contains("number 3"));
+}
+
+#[test]
+fn tail_recursion_doesnt_grow_the_stack() {
+    let src = "
+        fun countdown(n) { if (n == 0) return \"done\"; return countdown(n - 1); }
+        print countdown(1000000);
+        fun even(n) { if (n == 0) return true; return odd(n - 1); }
+        fun odd(n) { if (n == 0) return false; return even(n - 1); }
+        print even(100001);
+    ";
+    let out = common::run_program(src, &[]);
+    assert_eq!(
+        (out.code, out.stdout.as_str()),
+        (0, "done\nfalse\n"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn non_tail_recursion_is_still_limited() {
+    let src = "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); } print f(5000);";
+    let out = common::run_program(src, &[]);
+    assert_eq!(out.code, 70);
+    assert!(out.stderr.contains("Stack overflow."), "{}", out.stderr);
+}
@@ -0,0 +1,43 @@
+use codecrafters_interpreter::interpreter::Value;
+use codecrafters_interpreter::{run_source, run_source_with_globals, RunError};
+
+#[test]
+fn host_globals_are_visible_to_the_script() {
+    let globals = vec![
+        ("name", Value::String("host".into())),
+        ("limit", Value::Number(3.0)),
+    ];
+    let out = run_source_with_globals(
+        "for (var i = 0; i < limit; i++) print name + str(i);",
+        globals,
+    );
+    assert_eq!(out.ok().unwrap(), "host0\nhost1\nhost2\n");
+}
+
+#[test]
+fn scripts_can_reassign_host_globals() {
+    let out = run_source_with_globals(
+        "limit = limit * 2; print limit;",
+        vec![("limit", Value::Number(3.0))],
+    );
+    assert_eq!(out.ok().unwrap(), "6\n");
+}
+
+#[test]
+fn globals_are_not_defined_without_the_host() {
+    let err = run_source("print limit;").err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Undefined variable 'limit'.\n[line 1, col 7]"
+    );
+}
+
+#[test]
+fn exit_stops_the_script() {
+    assert_eq!(
+        run_source("print 1; exit(0); print 2;").ok().as_deref(),
+        Some("1\n")
+    );
+    let err = run_source("print 1; exit(3); print 2;").err().unwrap();
+    assert!(matches!(err, RunError::Exit(3)));
+}
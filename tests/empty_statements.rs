@@ -0,0 +1,56 @@
+mod common;
+
+use common::{run, run_binary};
+
+#[test]
+fn stray_semicolons_do_nothing() {
+    assert_eq!(run(";; print 1;; ;"), "1\n");
+    assert_eq!(run("{ ; } fun f() { ; return 2; } print f();"), "2\n");
+}
+
+#[test]
+fn empty_loop_bodies() {
+    let src = "
+        var i = 0;
+        fun step() { i = i + 1; return i < 5; }
+        while (step()) ;
+        print i;
+        var j = 0;
+        for (; j < 3; j = j + 1) ;
+        print j;
+        for (var k = 0; step() and k < 100; k = k + 1) ;
+        print i;
+    ";
+    assert_eq!(run(src), "5\n3\n6\n");
+}
+
+#[test]
+fn empty_branches() {
+    assert_eq!(
+        run("if (true) ; else print 1; if (false) print 2; else ;"),
+        ""
+    );
+}
+
+#[test]
+fn forever_loop_with_empty_body_parses() {
+    let out = run_binary(&["parse-program", "-"], "for (;;) ;");
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "(\n  (while true (empty))\n)\n");
+}
+
+#[test]
+fn empty_statement_in_ast_output() {
+    let out = run_binary(&["parse-program", "-"], ";");
+    assert_eq!(out.stdout, "(\n  (empty)\n)\n");
+    let out = run_binary(&["parse-program", "-", "--json"], ";");
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert!(
+        out.stdout.contains("{\"kind\": \"empty\"}"),
+        "{}",
+        out.stdout
+    );
+    let out = run_binary(&["run", "-", "--trace"], "; print 1;");
+    assert_eq!(out.stdout, "1\n");
+    assert!(!out.stderr.contains("block"), "{}", out.stderr);
+}
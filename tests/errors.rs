@@ -0,0 +1,140 @@
+mod common;
+
+use common::{run, run_binary, run_err, run_program};
+
+#[test]
+fn all_parse_errors_are_reported() {
+    let out = run_program("var = 1;\nprint ;\nvar x = 1\nprint \"ok\";", &[]);
+    assert_eq!(out.code, 65);
+    assert_eq!(out.stdout, "");
+    assert_eq!(
+        out.stderr,
+        "[line 1, col 5] Error at '=': Expecting var name\n\
+         [line 2, col 7] Error at ';': Unexpected token\n\
+         [line 4, col 1] Error at 'print': Expecting `;`\n"
+    );
+}
+
+#[test]
+fn redeclaration_in_the_same_scope_is_an_error() {
+    let err = run_err("var x = 1; var x = 2;");
+    assert_eq!(
+        err,
+        "[line 1, col 16] Error at 'x': Already a variable with this name in this scope."
+    );
+    let err = run_err("{ var x = 1; var x = 2; }");
+    assert_eq!(
+        err,
+        "[line 1, col 18] Error at 'x': Already a variable with this name in this scope."
+    );
+    let err = run_err("fun f(a) { var a = 1; }");
+    assert!(
+        err.contains("Error at 'a': Already a variable with this name in this scope."),
+        "{}",
+        err
+    );
+    assert_eq!(run_program("var x = 1; var x = 2;", &[]).code, 65);
+}
+
+#[test]
+fn shadowing_in_a_nested_block_is_allowed() {
+    assert_eq!(
+        run("var x = 1; { var x = 2; print x; { var x = 3; print x; } } print x;"),
+        "2\n3\n1\n"
+    );
+}
+
+#[test]
+fn reading_a_variable_in_its_own_initializer_is_a_static_error() {
+    let out = run_program("print 1;\nvar a = a;", &[]);
+    assert_eq!(out.code, 65);
+    assert_eq!(out.stdout, "");
+    assert_eq!(
+        out.stderr,
+        "[line 2, col 9] Error at 'a': Can't read global variable in its own initializer.\n"
+    );
+    assert_eq!(
+        run_err("{ var a = a; }"),
+        "[line 1, col 11] Error at 'a': Can't read local variable in its own initializer."
+    );
+    // Functions may refer to themselves, since they run later
+    assert_eq!(
+        run("var f = fun (n) { return n == 0 ? \"done\" : f(n - 1); }; print f(3);"),
+        "done\n"
+    );
+}
+
+#[test]
+fn malformed_groupings_report_where_they_break() {
+    assert_eq!(
+        run_err("print (1 +);"),
+        "[line 1, col 11] Error at ')': Unexpected token"
+    );
+    assert_eq!(
+        run_err("print (1\n+ 2"),
+        "[line 2, col 4] Error at end: Expecting `)` to close the `(` at line 1, col 7"
+    );
+    let out = run_binary(&["evaluate", "-"], "(1 +\n\n");
+    assert_eq!(out.code, 65);
+    assert_eq!(
+        out.stderr,
+        "[line 3, col 1] Error at end: Not expecting end of file\n"
+    );
+}
+
+#[test]
+fn runtime_errors_in_groupings_point_at_the_operator() {
+    assert_eq!(
+        run_err("print (1 +\n\"a\" * 2);"),
+        "Left operand must be a number, got string \"a\".\n[line 2, col 5]"
+    );
+}
+
+#[test]
+fn json_errors_for_each_stage() {
+    let cases = [
+        (
+            "print 1;\n@",
+            65,
+            "{\"stage\": \"scan\", \"line\": 2, \"column\": 1, \"message\": \"Unexpected character: @\"}\n",
+        ),
+        (
+            "print (;",
+            65,
+            "{\"stage\": \"parse\", \"line\": 1, \"column\": 8, \"message\": \"Unexpected token\"}\n",
+        ),
+        (
+            "{ var a = a; }",
+            65,
+            "{\"stage\": \"resolve\", \"line\": 1, \"column\": 11, \
+             \"message\": \"Can't read local variable in its own initializer.\"}\n",
+        ),
+        (
+            "print -\"x\";",
+            70,
+            "{\"stage\": \"runtime\", \"line\": 1, \"column\": 7, \
+             \"message\": \"Operand must be a number, got string \\\"x\\\".\"}\n",
+        ),
+    ];
+    for (src, code, stderr) in cases {
+        let out = run_program(src, &["--json-errors"]);
+        assert_eq!(out.code, code, "{}", src);
+        assert_eq!(out.stderr, stderr, "{}", src);
+    }
+}
+
+#[test]
+fn json_errors_escape_messages_and_print_one_per_line() {
+    let out = run_program("print \"\\q\";", &["--json-errors"]);
+    assert_eq!(
+        out.stderr,
+        "{\"stage\": \"scan\", \"line\": 1, \"column\": 10, \"message\": \"Invalid escape sequence: \\\\q\"}\n"
+    );
+    let out = run_program("var = 1;\nprint ;", &["--json-errors"]);
+    assert_eq!(out.code, 65);
+    assert_eq!(out.stderr.lines().count(), 2);
+    assert!(out
+        .stderr
+        .lines()
+        .all(|line| line.starts_with("{\"stage\": \"parse\"") && line.ends_with('}')));
+}
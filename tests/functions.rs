@@ -0,0 +1,140 @@
+mod common;
+
+use common::{run, run_binary, run_err};
+
+#[test]
+fn counter_closure() {
+    let src = "
+        fun makeCounter() {
+            var i = 0;
+            fun count() { i = i + 1; return i; }
+            return count;
+        }
+        var a = makeCounter();
+        var b = makeCounter();
+        print a(); print a(); print b(); print a();
+    ";
+    assert_eq!(run(src), "1\n2\n1\n3\n");
+}
+
+#[test]
+fn closures_share_the_captured_variable() {
+    let src = "
+        var get; var set;
+        fun make() {
+            var x = \"before\";
+            fun g() { return x; }
+            fun s(v) { x = v; }
+            get = g; set = s;
+        }
+        make();
+        set(\"after\");
+        print get();
+    ";
+    assert_eq!(run(src), "after\n");
+}
+
+#[test]
+fn closure_sees_the_scope_it_was_defined_in() {
+    let src = "
+        var a = \"global\";
+        {
+            fun show() { print a; }
+            show();
+            var a = \"block\";
+            show();
+        }
+    ";
+    assert_eq!(run(src), "global\nglobal\n");
+}
+
+#[test]
+fn lambda_passed_to_a_higher_order_function() {
+    let src = "
+        fun map(arr, f) {
+            var out = [];
+            foreach (x in arr) push(out, f(x));
+            return out;
+        }
+        print map([1, 2, 3], fun (x) { return x * 10; });
+        var add = fun (a, b) { return a + b; };
+        print add(2, 3);
+        print add;
+    ";
+    assert_eq!(run(src), "[10, 20, 30]\n5\n<anonymous fn>\n");
+}
+
+#[test]
+fn lambda_closes_over_locals() {
+    let src = "
+        fun adder(n) { return fun (x) { return x + n; }; }
+        print adder(5)(10);
+    ";
+    assert_eq!(run(src), "15\n");
+}
+
+#[test]
+fn duplicate_parameters_are_a_parse_error() {
+    let err = run_err("fun f(a, b, a) {}");
+    assert!(
+        err.contains("[line 1, col 13] Error at 'a': Already a parameter with this name."),
+        "{}",
+        err
+    );
+    let err = run_err("var f = fun (x, x) {};");
+    assert!(
+        err.contains("Already a parameter with this name."),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn too_many_parameters_are_a_parse_error() {
+    let params: Vec<String> = (0..256).map(|i| format!("p{}", i)).collect();
+    let err = run_err(&format!("fun f({}) {{}}", params.join(", ")));
+    assert!(
+        err.contains("Error at 'p255': Can't have more than 255 parameters."),
+        "{}",
+        err
+    );
+    let ok = format!(
+        "fun f({}) {{ return p254; }} print 1;",
+        params[..255].join(", ")
+    );
+    assert_eq!(run(&ok), "1\n");
+}
+
+#[test]
+fn wrong_number_of_arguments() {
+    assert!(run_err("fun f(a) {} f(1, 2);").contains("Expected 1 arguments but got 2."));
+}
+
+#[test]
+fn chained_property_access_parses() {
+    let out = run_binary(&["parse", "-"], "a.b.c");
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "(get (get (variable a) b) c)\n");
+}
+
+#[test]
+fn property_of_a_non_instance_is_an_error() {
+    let err = run_err("var a = 1; print a.b;");
+    assert!(
+        err.contains("Only instances have properties, got number 1 for '.b'."),
+        "{}",
+        err
+    );
+    let err = run_err("var s = \"x\"; s.len = 3;");
+    assert!(
+        err.contains("Only instances have fields, got string \"x\" for '.len'."),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn instance_fields() {
+    let src = "class P {} var p = P(); p.x = 1; p.y = p.x + 1; print p.y;";
+    assert_eq!(run(src), "2\n");
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use std::thread;
+
+use codecrafters_interpreter::interpreter::{evaluate, Interpreter, Interrupt};
+use codecrafters_interpreter::parser::{Expr, Grouping, Literal, MAX_NESTING};
+use codecrafters_interpreter::tokenizer::Pos;
+use common::run_program;
+
+#[test]
+fn unbounded_recursion_is_a_runtime_error() {
+    let out = run_program("fun f(n) { return 1 + f(n + 1); } f(0);", &[]);
+    assert_eq!(out.code, 70);
+    assert!(out.stderr.contains("Stack overflow."), "{}", out.stderr);
+}
+
+#[test]
+fn max_depth_sets_the_call_limit() {
+    let src = "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); } print f(50);";
+    let out = run_program(src, &["--max-depth=40"]);
+    assert_eq!(out.code, 70);
+    assert!(out.stderr.contains("Stack overflow."), "{}", out.stderr);
+    let out = run_program(src, &["--max-depth=60"]);
+    assert_eq!((out.code, out.stdout.as_str()), (0, "50\n"));
+}
+
+fn nested(open: &str, inner: &str, close: &str, n: usize) -> String {
+    format!("{}{}{}", open.repeat(n), inner, close.repeat(n))
+}
+
+#[test]
+fn pathological_nesting_is_a_parse_error() {
+    let programs = [
+        format!("print {};", nested("(", "1", ")", 100_000)),
+        format!("print {};", nested("- ", "1", "", 100_000)),
+        format!("print {};", nested("[", "", "]", 100_000)),
+        nested("{", "print 1;", "}", 100_000),
+        nested("if (true) ", "print 1;", "", 100_000),
+        format!("print {};", vec!["1"; 100_000].join(" + ")),
+        format!("var a = 1; {}1;", "a = ".repeat(100_000)),
+        format!("print {}1;", "true ? 1 : ".repeat(100_000)),
+    ];
+    for src in programs {
+        let out = run_program(&src, &[]);
+        assert_eq!(out.code, 65, "{}", &src[..40]);
+        assert!(out.stderr.contains("Too deeply nested."), "{}", out.stderr);
+    }
+}
+
+#[test]
+fn reasonable_nesting_runs() {
+    let src = format!("print {};", nested("(", "1", ")", MAX_NESTING - 1));
+    let out = run_program(&src, &["--max-depth=1"]);
+    assert_eq!(
+        (out.code, out.stdout.as_str()),
+        (0, "1\n"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn evaluating_a_deep_tree_is_a_runtime_error() {
+    // A tree which didn't come from the parser, so only the evaluator limits it
+    let result = thread::Builder::new()
+        .stack_size(256 << 20)
+        .spawn(|| {
+            let pos = Pos { line: 1, column: 1 };
+            let mut expr = Expr::Literal(pos, Literal::Nil);
+            for _ in 0..10 * MAX_NESTING {
+                expr = Expr::Grouping(pos, Grouping(Box::new(expr)));
+            }
+            let mut out = Vec::<u8>::new();
+            let result = evaluate(&expr, &mut Interpreter::new(&mut out));
+            match result {
+                Err(Interrupt::Error(err)) => Some(err.msg),
+                _ => None,
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(result.as_deref(), Some("Expression too deeply nested."));
+}
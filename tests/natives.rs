@@ -0,0 +1,221 @@
+mod common;
+
+use common::{run, run_binary, run_err, run_program};
+
+#[test]
+fn input_reads_lines_after_the_program_on_stdin() {
+    let out = run_program(
+        "print input();\nprint input();\nprint input();\n__END__\nhello\r\nworld",
+        &[],
+    );
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "hello\nworld\nnil\n");
+}
+
+#[test]
+fn input_without_end_line_gives_nil() {
+    let out = run_program("print input();", &[]);
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "nil\n");
+}
+
+#[test]
+fn input_reads_stdin_when_the_program_is_a_file() {
+    let path = std::env::temp_dir().join("lox-test-input.lox");
+    std::fs::write(&path, "var name = input(); print \"hi \" + name;").unwrap();
+    let out = run_binary(&["run", path.to_str().unwrap()], "lox\n");
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(out.stdout, "hi lox\n");
+}
+
+#[test]
+fn only_run_stops_at_the_end_line() {
+    let out = run_binary(&["tokenize", "-"], "x\n__END__\ny");
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(
+        out.stdout,
+        "IDENTIFIER x null\nIDENTIFIER __END__ null\nIDENTIFIER y null\nEOF  null\n"
+    );
+    let out = run_binary(&["parse-program", "-"], "print 1;\n__END__\n;print 2;");
+    assert_eq!(out.code, 0, "{}", out.stderr);
+    assert_eq!(
+        out.stdout,
+        "(\n  (print 1.0)\n  (expr (variable __END__))\n  (print 2.0)\n)\n"
+    );
+    let out = run_binary(&["--help"], "");
+    assert!(out.stdout.contains("`__END__`"), "{}", out.stdout);
+}
+
+#[test]
+fn type_names() {
+    let src = "
+        class A {}
+        print type(nil); print type(true); print type(1); print type(\"s\");
+        print type(clock); print type(fun () {}); print type([]); print type({});
+        print type(A); print type(A());
+    ";
+    assert_eq!(
+        run(src),
+        "nil\nbool\nnumber\nstring\nfunction\nfunction\narray\nmap\nclass\ninstance\n"
+    );
+}
+
+#[test]
+fn error_raises_a_runtime_error() {
+    assert_eq!(
+        run_err("print 1;\nerror(\"boom\");"),
+        "boom\n[line 2, col 6]"
+    );
+    let out = run_program("print 1; error(\"boom\"); print 2;", &[]);
+    assert_eq!(out.code, 70);
+    assert_eq!(out.stdout, "1\n");
+    assert_eq!(out.stderr, "boom\n[line 1, col 15]\n");
+}
+
+#[test]
+fn assert_checks_truthiness() {
+    assert_eq!(
+        run("assert(true); assert(1, \"msg\"); assert(\"\"); print \"ok\";"),
+        "ok\n"
+    );
+    assert_eq!(
+        run_err("assert(false);"),
+        "Assertion failed\n[line 1, col 7]"
+    );
+    assert_eq!(
+        run_err("assert(nil, \"custom\");"),
+        "custom\n[line 1, col 7]"
+    );
+    assert!(run_err("assert();").starts_with("Expected 1 to 2 arguments but got 0."));
+    assert!(run_err("assert(1, 2, 3);").starts_with("Expected 1 to 2 arguments but got 3."));
+}
+
+#[test]
+fn string_natives() {
+    assert_eq!(
+        run("print substr(\"héllo\", 1, 3); print upper(\"straße\"); print lower(\"ÀB\"); print substr(\"😀ab\", 0, 2);"),
+        "éll\nSTRASSE\nàb\n😀a\n"
+    );
+    assert!(run_err("print substr(\"abc\", 2, 5);")
+        .starts_with("substr() range [2, 7) is out of bounds for a string of length 3"));
+    assert!(run_err("print substr(\"abc\", -1, 1);")
+        .starts_with("substr() range [-1, 0) is out of bounds for a string of length 3"));
+    assert!(run_err("print upper(1);").starts_with("upper() expects a string, got number 1."));
+}
+
+#[test]
+fn conversions() {
+    assert_eq!(
+        run("print num(\"  12  \") + 1; print num(\"1e3\"); print str(1.5) + str(nil) + str([1, \"a\"]);"),
+        "13\n1000\n1.5nil[1, \"a\"]\n"
+    );
+    assert!(run_err("print num(\"abc\");").starts_with("num() can't convert \"abc\" to a number."));
+}
+
+#[test]
+fn push_and_pop_mutate_shared_arrays() {
+    let src = "
+        var a = []; var b = a;
+        push(b, 1); push(a, 2);
+        print a; print pop(b); print a; print push(a, 3);
+    ";
+    assert_eq!(run(src), "[1, 2]\n2\n[1]\nnil\n");
+    assert_eq!(
+        run_err("pop([]);"),
+        "pop() from an empty array.\n[line 1, col 4]"
+    );
+}
+
+#[test]
+fn seeded_random_numbers_repeat() {
+    let src = "var s = \"\"; for (var i = 0; i < 5; i++) s += str(randint(1, 6)) + \" \"; print s; print rand();";
+    let first = run_program(src, &["--seed=42"]);
+    let second = run_program(src, &["--seed=42"]);
+    assert_eq!(first.code, 0, "{}", first.stderr);
+    assert_eq!(first.stdout, second.stdout);
+    let other = run_program(src, &["--seed=7"]);
+    assert_ne!(first.stdout, other.stdout);
+    let out = run("for (var i = 0; i < 100; i++) { var x = rand(); assert(x >= 0 and x < 1); var n = randint(1, 3); assert(n == 1 or n == 2 or n == 3); } print \"ok\";");
+    assert_eq!(out, "ok\n");
+}
+
+#[test]
+fn randint_checks_its_bounds() {
+    assert!(run_err("print randint(3, 1);").starts_with("randint() range [3, 1] is empty."));
+    assert!(run_err("print randint(1.5, 3);")
+        .starts_with("randint() low bound must be an integer, got number 1.5."));
+}
+
+#[test]
+fn exit_sets_the_exit_code() {
+    let out = run_program("print 1; exit(3); print 2;", &[]);
+    assert_eq!((out.code, out.stdout.as_str()), (3, "1\n"));
+    assert_eq!(run_program("exit(0);", &[]).code, 0);
+    let out = run_program("exit(256);", &[]);
+    assert_eq!(out.code, 70);
+    assert!(out
+        .stderr
+        .starts_with("exit() code must be between 0 and 255, got 256."));
+    let out = run_program("exit(1.5);", &[]);
+    assert_eq!(out.code, 70);
+    assert!(out
+        .stderr
+        .starts_with("exit() code must be an integer, got number 1.5."));
+}
+
+#[test]
+fn exit_is_not_reported_as_an_error() {
+    let out = run_program("exit(3);", &["--json-errors"]);
+    assert_eq!((out.code, out.stderr.as_str()), (3, ""));
+    let out = run_program("print 1; exit(4); print 2;", &["--echo"]);
+    assert_eq!(
+        (out.code, out.stdout.as_str(), out.stderr.as_str()),
+        (4, "1\n", "")
+    );
+}
+
+#[test]
+fn math_natives() {
+    assert_eq!(
+        run("print min(1, 2); print max(1, 2); print abs(-3); print sqrt(16); print pow(2, 10); print sqrt(-1);"),
+        "1\n2\n3\n4\n1024\nNaN\n"
+    );
+    assert!(run_err("print min(\"a\", 1);")
+        .starts_with("min() argument must be a number, got string \"a\"."));
+}
+
+#[test]
+fn file_access_needs_allow_fs() {
+    let out = run_program("print readfile(\"Cargo.toml\");", &[]);
+    assert_eq!(out.code, 70);
+    assert!(out.stderr.starts_with(
+        "readfile() needs file access, which is disabled. Run with --allow-fs to enable it."
+    ));
+}
+
+#[test]
+fn write_and_read_a_file() {
+    let path = std::env::temp_dir().join(format!("lox-natives-{}.txt", std::process::id()));
+    let path = path.to_str().unwrap();
+    let src = format!(
+        "writefile(\"{0}\", \"héllo\\n\"); print readfile(\"{0}\");",
+        path
+    );
+    let out = run_program(&src, &["--allow-fs"]);
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(
+        (out.code, out.stdout.as_str()),
+        (0, "héllo\n\n"),
+        "{}",
+        out.stderr
+    );
+
+    let out = run_program("readfile(\"/nonexistent/file\");", &["--allow-fs"]);
+    assert_eq!(out.code, 70);
+    assert!(
+        out.stderr
+            .starts_with("readfile() failed to read \"/nonexistent/file\""),
+        "{}",
+        out.stderr
+    );
+}
@@ -0,0 +1,212 @@
+mod common;
+
+use codecrafters_interpreter::interpreter::Truthiness;
+use common::{run, run_binary, run_err, run_program, run_with};
+
+#[test]
+fn string_comparison() {
+    assert_eq!(
+        run("print \"apple\" < \"banana\"; print \"abc\" >= \"abd\"; print \"B\" < \"a\"; print \"a\" <= \"a\";"),
+        "true\nfalse\ntrue\ntrue\n"
+    );
+    let err = run_err("print \"a\" < 1;");
+    assert_eq!(
+        err,
+        "Operands must be two numbers or two strings, got string \"a\" and number 1.\n[line 1, col 11]"
+    );
+}
+
+#[test]
+fn string_concatenation_formats_numbers() {
+    assert_eq!(
+        run("print \"x = \" + 5; print 5 + \"3\"; print \"v\" + 1.5; print \"a\" + nil;"),
+        "x = 5\n53\nv1.5\nanil\n"
+    );
+}
+
+#[test]
+fn compound_assignment() {
+    let src = "
+        var x = 1;
+        x += 2; print x;
+        x -= 1; print x;
+        x *= 10; print x;
+        x /= 4; print x;
+        var s = \"a\"; s += \"b\"; print s;
+        var a = [1]; a[0] += 5; print a;
+    ";
+    assert_eq!(run(src), "3\n2\n20\n5\nab\n[6]\n");
+    assert!(run_err("1 += 2;").contains("Error at '+=': Invalid assignment target"));
+}
+
+#[test]
+fn equality() {
+    assert_eq!(
+        run("print nil == nil; print nil == false; print 1 == \"1\"; print 1 == 1.0; print 1 != \"1\";"),
+        "true\nfalse\nfalse\ntrue\ntrue\n"
+    );
+}
+
+#[test]
+fn nan_is_not_equal_to_itself() {
+    let out = run_with("print (0/0) == (0/0); print (0/0) != (0/0);", |ctx| {
+        ctx.ieee_div = true
+    });
+    assert_eq!(out, "false\ntrue\n");
+}
+
+#[test]
+fn postfix_increment_and_decrement() {
+    assert_eq!(
+        run("var i = 0; print i++; print i; print i--; print i; i++; i++; print i;"),
+        "0\n1\n1\n0\n2\n"
+    );
+    assert!(run_err("1++;").contains("[line 1, col 2] Error at '++': Invalid increment target"));
+    assert_eq!(
+        run_err("var s = \"a\";\ns++;"),
+        "Operand must be a number, got string \"a\".\n[line 2, col 1]"
+    );
+}
+
+#[test]
+fn bitwise_operators() {
+    assert_eq!(
+        run("print 6 & 3; print 6 | 3; print 6 ^ 3; print 1 << 4; print 256 >> 2; print -8 >> 1;"),
+        "2\n7\n5\n16\n64\n-4\n"
+    );
+    // Bitwise operators bind tighter than equality but looser than comparison
+    assert_eq!(run("print 1 | 2 == 3;"), "true\n");
+    assert_eq!(
+        run_err("print 1.5 & 1;"),
+        "Left operand must be an integer, got number 1.5.\n[line 1, col 11]"
+    );
+    assert_eq!(
+        run_err("print 1 << 64;"),
+        "Shift amount must be between 0 and 63\n[line 1, col 9]"
+    );
+    assert_eq!(
+        run_err("print 1 >> -1;"),
+        "Shift amount must be between 0 and 63\n[line 1, col 9]"
+    );
+}
+
+#[test]
+fn division_by_zero() {
+    assert_eq!(
+        run_err("print 1 / 0;"),
+        "Division by zero.\n[line 1, col 9]"
+    );
+    assert_eq!(
+        run_err("print 0 / 0;"),
+        "Division by zero.\n[line 1, col 9]"
+    );
+    let out = run_program("print 1 / 0;", &[]);
+    assert_eq!(out.code, 70);
+}
+
+#[test]
+fn ieee_division() {
+    let out = run_with("print 1 / 0; print -1 / 0;", |ctx| ctx.ieee_div = true);
+    assert_eq!(out, "inf\n-inf\n");
+    let out = run_program("print 1 / 0;", &["--ieee-div"]);
+    assert_eq!((out.code, out.stdout.as_str()), (0, "inf\n"));
+}
+
+#[test]
+fn errors_name_the_operand_types() {
+    assert_eq!(
+        run_err("print -\"a\";"),
+        "Operand must be a number, got string \"a\".\n[line 1, col 7]"
+    );
+    assert_eq!(
+        run_err("print 1 < true;"),
+        "Operands must be two numbers or two strings, got number 1 and bool true.\n[line 1, col 9]"
+    );
+    assert_eq!(
+        run_err("print 2 * \"x\";"),
+        "Right operand must be a number, got string \"x\".\n[line 1, col 9]"
+    );
+}
+
+#[test]
+fn array_concatenation() {
+    let src = "
+        var a = [1, 2]; var b = [3];
+        var c = a + b;
+        push(c, 4);
+        print a; print b; print c;
+    ";
+    assert_eq!(run(src), "[1, 2]\n[3]\n[1, 2, 3, 4]\n");
+    assert_eq!(
+        run_err("print [1] + 1;"),
+        "Operands must be two numbers, two arrays, or include a string, got array [1] and number 1.\n[line 1, col 11]"
+    );
+}
+
+#[test]
+fn nil_arithmetic() {
+    assert_eq!(
+        run_err("var x;\nprint x + 1;"),
+        "Left operand is nil. Can't do arithmetic on nil.\n[line 2, col 9]"
+    );
+    assert_eq!(
+        run_err("print 1 - nil;"),
+        "Right operand is nil. Can't do arithmetic on nil.\n[line 1, col 9]"
+    );
+}
+
+#[test]
+fn strict_truthiness_is_the_default() {
+    let src = "if (\"\") print \"t\"; else print \"f\"; if (0) print \"t\"; else print \"f\";";
+    assert_eq!(run(src), "t\nt\n");
+    let out = run_program(src, &["--truthy=strict"]);
+    assert_eq!(out.stdout, "t\nt\n");
+}
+
+#[test]
+fn loose_truthiness() {
+    let src = "
+        if (\"\") print \"t\"; else print \"f\";
+        if (0) print \"t\"; else print \"f\";
+        if ([]) print \"t\"; else print \"f\";
+        if (\"x\") print \"t\"; else print \"f\";
+        print \"\" or \"default\";
+        print !0;
+    ";
+    let out = run_with(src, |ctx| ctx.truthiness = Truthiness::Loose);
+    assert_eq!(out, "f\nf\nf\nt\ndefault\ntrue\n");
+    let out = run_program(src, &["--truthy=loose"]);
+    assert_eq!(out.stdout, "f\nf\nf\nt\ndefault\ntrue\n");
+}
+
+#[test]
+fn assert_follows_the_truthiness_mode() {
+    assert_eq!(run("assert(0); assert(\"\"); print \"ok\";"), "ok\n");
+    let out = run_program("assert(0, \"zero is falsy\");", &["--truthy=loose"]);
+    assert_eq!(out.code, 70);
+    assert_eq!(out.stderr, "zero is falsy\n[line 1, col 7]\n");
+    let out = run_with("assert(1); assert(\"x\"); print \"ok\";", |ctx| {
+        ctx.truthiness = Truthiness::Loose
+    });
+    assert_eq!(out, "ok\n");
+}
+
+#[test]
+fn chained_comparison() {
+    let err = run_err("print 1 < 2 < 3;");
+    assert!(
+        err.contains("Error at '<': Chained comparison '1 < 2 < 3' is not allowed; use explicit parentheses or 'and'."),
+        "{}",
+        err
+    );
+    assert_eq!(
+        run("print (1 < 2) == (2 < 3); print 1 < 2 and 2 < 3;"),
+        "true\ntrue\n"
+    );
+    // The parse command still shows the tree, as the codecrafters tests expect
+    let out = run_binary(&["parse", "-"], "83 < 99 < 115");
+    assert_eq!(
+        (out.code, out.stdout.as_str()),
+        (0, "(< (< 83.0 99.0) 115.0)\n")
+    );
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use codecrafters_interpreter::symbol::Symbol;
+use codecrafters_interpreter::tokenizer::{tokenize, tokenize_with_tab_width, Lexeme, TokenType};
+use common::{run, run_binary, run_err};
+
+fn types(src: &str) -> Vec<TokenType> {
+    let (tokens, errors) = tokenize(src);
+    assert!(
+        errors.is_empty(),
+        "{:?}",
+        errors.iter().map(|e| e.to_string()).collect::<Vec<_>>()
+    );
+    tokens.iter().map(|t| t.token_type).collect()
+}
+
+fn scan_errors(src: &str) -> Vec<String> {
+    tokenize(src).1.iter().map(|e| e.to_string()).collect()
+}
+
+#[test]
+fn nested_block_comments() {
+    assert_eq!(
+        types("/* a /* b */ c */ x"),
+        [TokenType::Identifier, TokenType::Eof]
+    );
+    assert_eq!(run("/* outer /* inner */ still comment */ print 1;"), "1\n");
+}
+
+#[test]
+fn block_comment_counts_lines() {
+    let (tokens, _) = tokenize("/* one\ntwo\n/* three\n*/ */ x");
+    assert_eq!(tokens[0].line, 4);
+}
+
+#[test]
+fn unterminated_block_comment() {
+    assert_eq!(
+        scan_errors("/* a /* b */"),
+        ["[line 1, col 13] Error: Unterminated block comment."]
+    );
+}
+
+#[test]
+fn multi_line_token_reports_its_start_line() {
+    let (tokens, errors) = tokenize("\"ab\ncd\" x");
+    assert!(errors.is_empty());
+    assert_eq!(
+        (tokens[0].token_type, tokens[0].line),
+        (TokenType::StringLiteral, 1)
+    );
+    assert_eq!(
+        (tokens[1].token_type, tokens[1].line),
+        (TokenType::Identifier, 2)
+    );
+    assert_eq!(run("print \"a\nb\";"), "a\nb\n");
+}
+
+#[test]
+fn unterminated_string_reports_the_opening_line() {
+    assert_eq!(
+        scan_errors("print 1;\n\"never\nclosed\n"),
+        ["[line 2, col 1] Error: Unterminated string."]
+    );
+}
+
+#[test]
+fn number_underscores() {
+    assert_eq!(
+        run("print 1_000_000; print 1_000.5; print 1_0.2_5;"),
+        "1000000\n1000.5\n10.25\n"
+    );
+    assert_eq!(
+        types("1_"),
+        [TokenType::Number, TokenType::Identifier, TokenType::Eof]
+    );
+    assert_eq!(types("_1"), [TokenType::Identifier, TokenType::Eof]);
+    assert_eq!(
+        types("1__0"),
+        [TokenType::Number, TokenType::Identifier, TokenType::Eof]
+    );
+    assert!(run_err("print 1__0;").contains("Error at '__0'"));
+}
+
+#[test]
+fn scientific_notation() {
+    assert_eq!(
+        run("print 1e3; print 2.5e-3; print 1E+2; print 1_0e1;"),
+        "1000\n0.0025\n100\n100\n"
+    );
+    assert_eq!(types("1e10"), [TokenType::Number, TokenType::Eof]);
+    assert_eq!(
+        scan_errors("1e;"),
+        ["[line 1, col 3] Error: Expecting exponent digits."]
+    );
+    assert_eq!(
+        scan_errors("1e+"),
+        ["[line 1, col 4] Error: Expecting exponent digits."]
+    );
+}
+
+#[test]
+fn unicode_escapes() {
+    assert_eq!(run("print \"\\u{48}i \\u{1F600} \\u{e9}\";"), "Hi 😀 é\n");
+    assert_eq!(
+        scan_errors("\"\\u{}\""),
+        ["[line 1, col 4] Error: Expecting 1 to 6 hex digits and '}' in \\u{...}"]
+    );
+    assert_eq!(
+        scan_errors("\"\\u{41\""),
+        ["[line 1, col 4] Error: Expecting 1 to 6 hex digits and '}' in \\u{...}"]
+    );
+    assert_eq!(
+        scan_errors("\"\\u{D800}\""),
+        ["[line 1, col 4] Error: Invalid unicode code point: \\u{D800}"]
+    );
+    assert_eq!(
+        scan_errors("\n\"\\u{110000}\""),
+        ["[line 2, col 4] Error: Invalid unicode code point: \\u{110000}"]
+    );
+}
+
+#[test]
+fn number_formatting() {
+    assert_eq!(
+        run("print 100; print 100.5; print 0.1; print 0.1 + 0.2; print 1234567; print 1e20; print 1e21; print 1/3;"),
+        "100\n100.5\n0.1\n0.30000000000000004\n1234567\n1e20\n1e21\n0.3333333333333333\n"
+    );
+    let out = run_binary(&["tokenize", "-"], "100 100.5 1e20");
+    assert_eq!(
+        out.stdout,
+        "NUMBER 100 100.0\nNUMBER 100.5 100.5\nNUMBER 1e20 1e20\nEOF  null\n"
+    );
+}
+
+#[test]
+fn parse_and_evaluate_agree_on_numbers() {
+    let out = run_binary(&["parse", "-"], "0.1 + 0.2");
+    assert_eq!(out.stdout, "(+ 0.1 0.2)\n");
+    let out = run_binary(&["evaluate", "-"], "1234567");
+    assert_eq!(out.stdout, "1234567\n");
+}
+
+#[test]
+fn tabs_advance_to_the_next_tab_stop() {
+    let (_, errors) = tokenize_with_tab_width(" \t  @", 4);
+    assert_eq!(errors[0].pos.column, 7);
+    let (_, errors) = tokenize(" \t  @");
+    assert_eq!(errors[0].pos.column, 11);
+    let out = run_binary(&["tokenize", "--tab-width", "4", "-"], "\t\t@");
+    assert_eq!(out.code, 65);
+    assert_eq!(
+        out.stderr,
+        "[line 1, col 9] Error: Unexpected character: @\n"
+    );
+}
+
+#[test]
+fn only_names_are_interned() {
+    let (tokens, _) = tokenize("var x = \"a long string\" + 12.5;");
+    let interned: Vec<bool> = tokens
+        .iter()
+        .map(|token| matches!(token.lexeme, Lexeme::Name(_)))
+        .collect();
+    assert_eq!(
+        interned,
+        [true, true, false, false, false, false, false, false]
+    );
+    assert_eq!(tokens[1].name(), Symbol::intern("x"));
+    assert_eq!(tokens[3].lexeme.as_str(), "\"a long string\"");
+    assert_eq!(Symbol::intern(&String::from("this")), Symbol::this());
+    assert_ne!(Symbol::intern("init"), Symbol::super_());
+    assert_eq!(Symbol::init().as_str(), "init");
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{run, run_err};
+
+#[test]
+fn runs_first_matching_case_only() {
+    let src = "
+        fun name(n) {
+            switch (n) {
+                case 1: return \"one\";
+                case 2: return \"two\";
+                case 2: return \"second two\";
+                default: return \"many\";
+            }
+        }
+        print name(1); print name(2); print name(7);
+    ";
+    assert_eq!(run(src), "one\ntwo\nmany\n");
+}
+
+#[test]
+fn no_fallthrough() {
+    let src = "switch (1) { case 1: print \"a\"; case 2: print \"b\"; default: print \"c\"; }";
+    assert_eq!(run(src), "a\n");
+}
+
+#[test]
+fn default_runs_when_no_case_matches() {
+    assert_eq!(
+        run("switch (\"x\") { case \"y\": print 1; default: print 2; }"),
+        "2\n"
+    );
+    // The default may come before the cases
+    assert_eq!(
+        run("switch (3) { default: print 2; case 3: print 3; }"),
+        "3\n"
+    );
+}
+
+#[test]
+fn no_match_and_no_default_does_nothing() {
+    assert_eq!(
+        run("switch (3) { case 1: print 1; } print \"after\";"),
+        "after\n"
+    );
+}
+
+#[test]
+fn cases_compare_with_equality() {
+    assert_eq!(
+        run("switch ([1, 2]) { case [1, 2]: print \"same\"; }"),
+        "same\n"
+    );
+    assert_eq!(
+        run("switch (nil) { case false: print 1; default: print 2; }"),
+        "2\n"
+    );
+}
+
+#[test]
+fn each_case_is_a_scope() {
+    let src = "
+        var x = \"outer\";
+        switch (1) { case 1: var x = \"inner\"; print x; }
+        print x;
+        switch (2) { case 1: var y = 1; case 2: var y = 2; print y; }
+    ";
+    assert_eq!(run(src), "inner\nouter\n2\n");
+}
+
+#[test]
+fn break_ends_only_the_switch() {
+    let src = "
+        var i = 0;
+        while (i < 3) {
+            i = i + 1;
+            switch (i) {
+                case 2: print \"two\"; break; print \"unreachable\";
+                default: print i;
+            }
+        }
+        print \"done\";
+    ";
+    assert_eq!(run(src), "1\ntwo\n3\ndone\n");
+}
+
+#[test]
+fn break_in_switch_outside_loop() {
+    assert_eq!(
+        run("switch (1) { case 1: print 1; break; } print 2;"),
+        "1\n2\n"
+    );
+}
+
+#[test]
+fn continue_goes_to_the_enclosing_loop() {
+    let src = "
+        for (var i = 0; i < 3; i = i + 1) {
+            switch (i) { case 1: continue; }
+            print i;
+        }
+    ";
+    assert_eq!(run(src), "0\n2\n");
+    assert!(run_err("switch (1) { case 1: continue; }")
+        .contains("Can't use 'continue' outside of a loop."));
+}
+
+#[test]
+fn break_in_function_inside_switch_is_an_error() {
+    assert!(run_err("switch (1) { case 1: fun f() { break; } }")
+        .contains("Can't use 'break' outside of a loop."));
+}
+
+#[test]
+fn only_one_default() {
+    assert!(
+        run_err("switch (1) { default: print 1; default: print 2; }")
+            .contains("A switch can only have one default.")
+    );
+}
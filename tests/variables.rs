@@ -0,0 +1,45 @@
+mod common;
+
+use common::{run, run_err, run_with};
+
+#[test]
+fn implicit_global_in_block_keeps_locals_in_place() {
+    let src = "{ x = 1; var y = 2; print y; } print x;";
+    let out = run_with(src, |ctx| ctx.implicit_globals = true);
+    assert_eq!(out, "2\n1\n");
+}
+
+#[test]
+fn implicit_global_in_function() {
+    let src = "fun f() { var a = 1; g = a + 1; var b = 3; print b; } f(); print g;";
+    let out = run_with(src, |ctx| ctx.implicit_globals = true);
+    assert_eq!(out, "3\n2\n");
+}
+
+#[test]
+fn assigning_undeclared_variable_fails_by_default() {
+    assert!(run_err("x = 1;").contains("Variable 'x' not declared before assignment"));
+}
+
+#[test]
+fn const_can_be_read() {
+    assert_eq!(run("const a = 3; print a * 2;"), "6\n");
+}
+
+#[test]
+fn const_cant_be_assigned() {
+    assert!(run_err("const a = 3; a = 4;").contains("Can't assign to constant 'a'."));
+    assert!(run_err("{ const a = 3; a += 1; }").contains("Can't assign to constant 'a'."));
+}
+
+#[test]
+fn const_needs_initializer() {
+    assert!(run_source_fails_to_parse("const a;"));
+}
+
+fn run_source_fails_to_parse(src: &str) -> bool {
+    matches!(
+        codecrafters_interpreter::run_source(src),
+        Err(codecrafters_interpreter::RunError::Parse(_))
+    )
+}